@@ -0,0 +1,249 @@
+//! Runs the Intcode workloads of days 2, 5 and 7, and a long countdown loop,
+//! with and without the decode cache and on a reference copy of the original
+//! interpreter. The puzzle inputs are used if they are in `inputs/`, the
+//! examples from the puzzle descriptions otherwise.
+
+use std::fs;
+
+use aoc19::intcode::{network::Network, Program};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const DAY2_EXAMPLE: &str = "1,9,10,3,2,3,11,0,99,30,40,50";
+const DAY5_EXAMPLE: &str = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,\
+    0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99";
+const DAY7_EXAMPLE: &str = "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,\
+    1005,28,6,99,0,0,5";
+
+/// Counts a word down to zero, executing two instructions per iteration.
+const COUNTDOWN: &str = "1001,8,-1,8,1005,8,0,99,100000";
+
+fn load(day: &str, example: &str) -> Program {
+    fs::read_to_string(format!("inputs/{day}.in"))
+        .unwrap_or_else(|_| example.to_owned())
+        .trim()
+        .parse()
+        .unwrap()
+}
+
+fn variants(program: &Program) -> [(&'static str, Program); 2] {
+    [false, true].map(|cached| {
+        let mut p = program.clone();
+        p.set_decode_cache(cached);
+        (if cached { "cached" } else { "uncached" }, p)
+    })
+}
+
+/// Searches noun/verb pairs like `day2::part_b`, running a fresh clone of the
+/// program for every pair.
+fn day2(c: &mut Criterion) {
+    let program = load("day2", DAY2_EXAMPLE);
+    let limit = program.code.len().min(100) as i64;
+    let mut group = c.benchmark_group("day2");
+    let code = program.code.to_vec();
+    group.bench_function("baseline", |b| {
+        b.iter(|| {
+            for noun in 0..limit {
+                for verb in 0..limit {
+                    let mut code = code.clone();
+                    code[1] = noun;
+                    code[2] = verb;
+                    black_box(baseline::Interpreter::new(code).execute(&[]));
+                }
+            }
+        })
+    });
+    for (name, p) in variants(&program) {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for noun in 0..limit {
+                    for verb in 0..limit {
+                        let mut q = p.clone();
+                        q.code[1] = noun;
+                        q.code[2] = verb;
+                        black_box(q.execute(&[]).ok());
+                    }
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Runs the diagnostic program once, like `day5::part_b`.
+fn day5(c: &mut Criterion) {
+    let program = load("day5", DAY5_EXAMPLE);
+    let mut group = c.benchmark_group("day5");
+    let code = program.code.to_vec();
+    group.bench_function("baseline", |b| {
+        b.iter(|| black_box(baseline::Interpreter::new(code.clone()).execute(&[5])))
+    });
+    for (name, p) in variants(&program) {
+        group.bench_function(name, |b| b.iter(|| black_box(p.clone().execute(&[5]))));
+    }
+    group.finish();
+}
+
+/// Runs one feedback loop of amplifiers, like a single permutation of
+/// `day7::part_b`.
+fn day7(c: &mut Criterion) {
+    let program = load("day7", DAY7_EXAMPLE);
+    let mut group = c.benchmark_group("day7");
+    let code = program.code.to_vec();
+    group.bench_function("baseline", |b| {
+        b.iter(|| {
+            let mut amplifiers: Vec<_> = [9, 8, 7, 6, 5]
+                .map(|phase| (baseline::Interpreter::new(code.clone()), vec![phase]))
+                .into();
+            let mut signal = 0;
+            loop {
+                let mut exited = false;
+                for (amplifier, input) in &mut amplifiers {
+                    input.push(signal);
+                    let (done, output) = amplifier.execute(input).unwrap();
+                    input.clear();
+                    exited = done;
+                    signal = *output.last().unwrap();
+                }
+                if exited {
+                    break black_box(signal);
+                }
+            }
+        })
+    });
+    for (name, p) in variants(&program) {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut network = Network::new();
+                for phase in [9, 8, 7, 6, 5] {
+                    let amplifier = network.add(p.clone());
+                    network.feed(amplifier, phase);
+                    if amplifier > 0 {
+                        network.connect(amplifier - 1, amplifier);
+                    }
+                }
+                network.connect(4, 0);
+                network.feed(0, 0);
+                black_box(network.run().ok());
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Runs a long loop that does nothing but arithmetic and jumps, so that only
+/// the speed of the interpreter itself is measured.
+fn countdown(c: &mut Criterion) {
+    let program: Program = COUNTDOWN.parse().unwrap();
+    let mut group = c.benchmark_group("countdown");
+    let code = program.code.to_vec();
+    group.bench_function("baseline", |b| {
+        b.iter(|| black_box(baseline::Interpreter::new(code.clone()).execute(&[])))
+    });
+    for (name, p) in variants(&program) {
+        group.bench_function(name, |b| b.iter(|| black_box(p.clone().execute(&[]))));
+    }
+    group.finish();
+}
+
+/// The interpreter as it was before [`Program`] learned to step, trace and
+/// share memory: a plain vector of words and a loop over a `match`, as a
+/// yardstick for what all of that costs.
+mod baseline {
+    pub struct Interpreter {
+        code: Vec<i64>,
+        pc: usize,
+        relative_base: i64,
+    }
+
+    impl Interpreter {
+        pub fn new(code: Vec<i64>) -> Interpreter {
+            Interpreter {
+                code,
+                pc: 0,
+                relative_base: 0,
+            }
+        }
+
+        /// Runs until the program exits, which is reported as `true`, or wants
+        /// more input than `input` holds. Returns `None` on anything invalid.
+        pub fn execute(&mut self, input: &[i64]) -> Option<(bool, Vec<i64>)> {
+            let mut input = input.iter();
+            let mut output = Vec::new();
+            loop {
+                let value = self.fetch();
+                let mut modes = value / 100;
+                match value % 100 {
+                    opcode @ (1 | 2 | 7 | 8) => {
+                        let lhs = self.parameter(&mut modes)?;
+                        let rhs = self.parameter(&mut modes)?;
+                        let target = self.target(&mut modes)?;
+                        *self.get_mut(target) = match opcode {
+                            1 => lhs.checked_add(rhs)?,
+                            2 => lhs.checked_mul(rhs)?,
+                            7 => (lhs < rhs).into(),
+                            _ => (lhs == rhs).into(),
+                        };
+                    }
+                    3 => {
+                        let target = self.target(&mut modes)?;
+                        let Some(&value) = input.next() else {
+                            self.pc -= 2;
+                            return Some((false, output));
+                        };
+                        *self.get_mut(target) = value;
+                    }
+                    4 => output.push(self.parameter(&mut modes)?),
+                    opcode @ (5 | 6) => {
+                        let value = self.parameter(&mut modes)?;
+                        let target = self.parameter(&mut modes)?;
+                        if (value != 0) == (opcode == 5) {
+                            self.pc = target.try_into().ok()?;
+                        }
+                    }
+                    9 => self.relative_base += self.parameter(&mut modes)?,
+                    99 => {
+                        self.pc -= 1;
+                        return Some((true, output));
+                    }
+                    _ => return None,
+                }
+            }
+        }
+
+        fn parameter(&mut self, modes: &mut i64) -> Option<i64> {
+            if *modes % 10 == 1 {
+                *modes /= 10;
+                return Some(self.fetch());
+            }
+            let address = self.target(modes)?;
+            Some(self.code.get(address).copied().unwrap_or(0))
+        }
+
+        fn target(&mut self, modes: &mut i64) -> Option<usize> {
+            let mode = *modes % 10;
+            *modes /= 10;
+            let raw = self.fetch();
+            match mode {
+                0 => raw.try_into().ok(),
+                2 => (self.relative_base + raw).try_into().ok(),
+                _ => None,
+            }
+        }
+
+        fn fetch(&mut self) -> i64 {
+            let word = self.code.get(self.pc).copied().unwrap_or(0);
+            self.pc += 1;
+            word
+        }
+
+        fn get_mut(&mut self, address: usize) -> &mut i64 {
+            if address >= self.code.len() {
+                self.code.resize(address + 1, 0);
+            }
+            &mut self.code[address]
+        }
+    }
+}
+
+criterion_group!(benches, day2, day5, day7, countdown);
+criterion_main!(benches);
@@ -0,0 +1,77 @@
+//! Compares the paged copy-on-write memory behind `Program` with a flat `Vec`.
+
+use aoc19::intcode::Memory;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const SIZES: [usize; 3] = [1 << 8, 1 << 12, 1 << 16];
+
+/// Clones memory and writes a single word, as a state-space search does when
+/// it branches off a program.
+fn clone_and_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("clone_and_write");
+    for size in SIZES {
+        let words: Vec<i64> = (0..size as i64).collect();
+        let flat = words.clone();
+        let paged = Memory::from(words);
+        group.bench_with_input(BenchmarkId::new("vec", size), &flat, |b, flat| {
+            b.iter(|| {
+                let mut copy = flat.clone();
+                copy[size / 2] = 1;
+                black_box(copy)
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("paged", size), &paged, |b, paged| {
+            b.iter(|| {
+                let mut copy = paged.clone();
+                copy[size / 2] = 1;
+                black_box(copy)
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Reads every word in order, which is the price paged memory pays for cheap
+/// clones.
+fn sequential_read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sequential_read");
+    for size in SIZES {
+        let words: Vec<i64> = (0..size as i64).collect();
+        let flat = words.clone();
+        let paged = Memory::from(words);
+        group.bench_with_input(BenchmarkId::new("vec", size), &flat, |b, flat| {
+            b.iter(|| (0..size).map(|a| flat[a]).sum::<i64>())
+        });
+        group.bench_with_input(BenchmarkId::new("paged", size), &paged, |b, paged| {
+            b.iter(|| (0..size).map(|a| paged[a]).sum::<i64>())
+        });
+    }
+    group.finish();
+}
+
+/// Writes every word in order to memory that is not shared with a clone.
+fn sequential_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sequential_write");
+    for size in SIZES {
+        let mut flat = vec![0; size];
+        let mut paged = Memory::from(vec![0; size]);
+        group.bench_function(BenchmarkId::new("vec", size), |b| {
+            b.iter(|| {
+                for (a, word) in flat.iter_mut().enumerate() {
+                    *word = a as i64;
+                }
+            })
+        });
+        group.bench_function(BenchmarkId::new("paged", size), |b| {
+            b.iter(|| {
+                for a in 0..size {
+                    paged[a] = a as i64;
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, clone_and_write, sequential_read, sequential_write);
+criterion_main!(benches);
@@ -1,4 +1,4 @@
-use crate::intcode::Program;
+use crate::intcode::{Budget, Program, ProgramState};
 use anyhow::{Error, Result};
 
 pub fn part_a(input: &str) -> Result<String> {
@@ -10,15 +10,23 @@ pub fn part_a(input: &str) -> Result<String> {
     Ok(format!("{ans}"))
 }
 
+/// Bad noun/verb pairs can turn the program into one that never halts.
+const MAX_INSTRUCTIONS: u64 = 100_000;
+
 pub fn part_b(input: &str) -> Result<String> {
-    let p: Program = input.trim().parse()?;
+    let mut p: Program = input.trim().parse()?;
+    p.set_budget(Budget {
+        instructions: Some(MAX_INSTRUCTIONS),
+        ..Budget::default()
+    });
 
     for noun in 0..=99 {
         for verb in 0..=99 {
             let mut q = p.clone();
             q.code[1] = noun;
             q.code[2] = verb;
-            if q.execute(&[]).is_ok() && q.code[0] == 19_690_720 {
+            let exited = matches!(q.execute(&[]), Ok((ProgramState::Exited, _)));
+            if exited && q.code[0] == 19_690_720 {
                 let ans = 100 * noun + verb;
                 return Ok(format!("{ans}"));
             }
@@ -0,0 +1,36 @@
+use anyhow::Result;
+
+use crate::intcode::{
+    network::packet::{PacketNetwork, NAT},
+    Program,
+};
+
+const COMPUTERS: usize = 50;
+
+pub fn part_a(input: &str) -> Result<String> {
+    let p: Program = input.trim().parse()?;
+    let mut network = PacketNetwork::boot(&p, COMPUTERS);
+
+    loop {
+        let round = network.round()?;
+        if let Some(packet) = round.sent.iter().find(|p| p.destination == NAT) {
+            return Ok(packet.y.to_string());
+        }
+    }
+}
+
+pub fn part_b(input: &str) -> Result<String> {
+    let p: Program = input.trim().parse()?;
+    let mut network = PacketNetwork::boot(&p, COMPUTERS);
+
+    let mut last = None;
+    loop {
+        let round = network.round()?;
+        if let Some(packet) = round.woken {
+            if last == Some(packet.y) {
+                return Ok(packet.y.to_string());
+            }
+            last = Some(packet.y);
+        }
+    }
+}
@@ -1,9 +1,10 @@
 use anyhow::{Error, Result};
 
-use crate::intcode::Program;
+use crate::intcode::{InputMode, Program};
 
 pub fn part_a(input: &str) -> Result<String> {
     let mut p: Program = input.trim().parse()?;
+    p.set_input_mode(InputMode::Strict);
     let (_, output) = p.execute(&[1])?;
     let ans = *output.last().ok_or(Error::msg("Empty output"))?;
     Ok(format!("{ans}"))
@@ -11,6 +12,7 @@ pub fn part_a(input: &str) -> Result<String> {
 
 pub fn part_b(input: &str) -> Result<String> {
     let mut p: Program = input.trim().parse()?;
+    p.set_input_mode(InputMode::Strict);
     let (_, output) = p.execute(&[5])?;
     let ans = *output.last().ok_or(Error::msg("Empty output"))?;
     Ok(format!("{ans}"))
@@ -1,6 +1,6 @@
 use std::cmp;
 
-use crate::intcode::{Program, ProgramState};
+use crate::intcode::{network::Network, Program};
 use anyhow::{Error, Result};
 
 fn find_longest_decreasing_suffix<T: Ord>(s: &[T]) -> Option<usize> {
@@ -31,6 +31,31 @@ fn next_permutation<T: Ord>(s: &mut [T]) -> bool {
     true
 }
 
+/// Runs one amplifier per phase setting, each feeding the next, and returns
+/// the last signal the final amplifier sends.
+fn amplify(p: &Program, phases: &[i64], feedback: bool) -> Result<i64> {
+    let mut network = Network::new();
+    for &phase in phases {
+        let amplifier = network.add(p.clone());
+        network.feed(amplifier, phase);
+    }
+    for amplifier in 1..phases.len() {
+        network.connect(amplifier - 1, amplifier);
+    }
+    let last = phases.len() - 1;
+    if feedback {
+        network.connect(last, 0);
+    }
+
+    network.feed(0, 0);
+    network.run()?;
+    network
+        .outputs(last)
+        .last()
+        .copied()
+        .ok_or(Error::msg("Missing output"))
+}
+
 pub fn part_a(input: &str) -> Result<String> {
     let p: Program = input.trim().parse()?;
 
@@ -38,12 +63,7 @@ pub fn part_a(input: &str) -> Result<String> {
 
     let mut best = None;
     loop {
-        let mut last = 0;
-        for &phase in &v {
-            let mut q = p.clone();
-            let (_, outp) = q.execute(&[phase, last])?;
-            last = *outp.get(0).ok_or(Error::msg("Missing output"))?;
-        }
+        let last = amplify(&p, &v, false)?;
 
         best = match best {
             None => Some(last),
@@ -66,27 +86,7 @@ pub fn part_b(input: &str) -> Result<String> {
 
     let mut best = None;
     loop {
-        let mut qs = Vec::new();
-        for &phase in &v {
-            let mut q = p.clone();
-            let (ProgramState::ExpectingInput, _) = q.execute(&[phase])? else {
-                return Err(Error::msg("Early halt"));
-            };
-            qs.push(q);
-        }
-
-        let mut last = 0;
-        loop {
-            let mut last_state = None;
-            for q in &mut qs {
-                let (res, outp) = q.execute(&[last])?;
-                last_state = Some(res);
-                last = *outp.get(0).ok_or(Error::msg("Missing output"))?;
-            }
-            if last_state.is_some_and(|s| s == ProgramState::Exited) {
-                break;
-            }
-        }
+        let last = amplify(&p, &v, true)?;
 
         best = match best {
             None => Some(last),
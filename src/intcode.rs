@@ -1,120 +1,497 @@
 use std::{
+    fmt,
     num::{ParseIntError, TryFromIntError},
+    ops::Range,
     str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 
-#[derive(Clone)]
-pub struct Program {
-    pub code: Vec<i64>,
+use device::{Device, DeviceError, Devices};
+use dialect::{Dialect, DynInput, DynOutput, Effects, Machine};
+
+pub mod ascii;
+pub mod asm;
+mod cache;
+pub mod cfg;
+pub mod cli;
+pub mod debugger;
+pub mod device;
+pub mod dialect;
+pub mod disasm;
+pub mod io;
+pub mod memory;
+pub mod network;
+pub mod profile;
+pub mod snapshot;
+pub mod trace;
+mod varint;
+pub mod word;
+
+pub use io::{Input, Output};
+pub use memory::Memory;
+pub use word::Word;
+
+pub struct Program<W: Word = i64> {
+    pub code: Memory<W>,
     program_counter: usize,
+    relative_base: i64,
+    trace: Option<trace::Trace<W>>,
+    profile: Option<profile::Profile>,
+    budget: Budget,
+    overflow_policy: OverflowPolicy,
+    input_mode: InputMode,
+    dialect: Dialect<W>,
+    devices: Devices<W>,
+    decode_cache: Option<cache::DecodeCache>,
+    executed: u64,
 }
 
-impl Program {
-    pub fn execute(&mut self, input: &[i64]) -> Result<(ProgramState, Vec<i64>), ExecutionError> {
+impl<W: Word> Program<W> {
+    pub fn execute(&mut self, input: &[W]) -> Result<(ProgramState, Vec<W>), Fault> {
         let mut output = Vec::new();
-        let mut input_it = input.iter();
-        loop {
-            let savepoint = self.program_counter;
-            let Instruction {
-                opcode,
-                mut mode_flag,
-            } = self.fetch_instruction()?;
-            match opcode {
-                Opcode::Arithmetic(op) => {
-                    let lhs = self.fetch_parameter(&mut mode_flag)?;
-                    let rhs = self.fetch_parameter(&mut mode_flag)?;
-                    let target_pos = self.fetch_positional_parameter(&mut mode_flag)?;
-
-                    let result = match op {
-                        ArithmeticOperation::Add => lhs + rhs,
-                        ArithmeticOperation::Mul => lhs * rhs,
-                    };
-
-                    *self.get_mut(target_pos)? = result;
-                }
-
-                Opcode::Store => {
-                    let pos = self.fetch_positional_parameter(&mut mode_flag)?;
-                    let Some(&inp) = input_it.next() else {
-                        self.program_counter = savepoint;
-                        return Ok((ProgramState::ExpectingInput, output));
-                    };
+        let state = self.run(&mut io::Iter(input.iter().cloned()), &mut output)?;
+        Ok((state, output))
+    }
 
-                    *self.get_mut(pos)? = inp;
-                }
+    /// Runs the program until it exits, `input` runs dry or the [`Budget`] is
+    /// used up, reading input and emitting output as the program asks for it.
+    pub fn run<I, O>(&mut self, input: &mut I, output: &mut O) -> Result<ProgramState, Fault>
+    where
+        I: Input<W> + ?Sized,
+        O: Output<W> + ?Sized,
+    {
+        self.run_while(input, output, false)
+    }
 
-                Opcode::Jump(cond) => {
-                    let lhs = self.fetch_parameter(&mut mode_flag)?;
-                    let rhs = self.fetch_parameter(&mut mode_flag)?;
+    /// Like [`Program::run`], but also stops with
+    /// [`ProgramState::ProducedOutput`] right after every value written to
+    /// `output`, so the caller can react to it before the program continues.
+    pub fn run_until_output<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<ProgramState, Fault>
+    where
+        I: Input<W> + ?Sized,
+        O: Output<W> + ?Sized,
+    {
+        self.run_while(input, output, true)
+    }
 
-                    let condition_satisfied = match cond {
-                        JumpCondition::True => lhs != 0,
-                        JumpCondition::False => lhs == 0,
-                    };
+    fn run_while<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        stop_on_output: bool,
+    ) -> Result<ProgramState, Fault>
+    where
+        I: Input<W> + ?Sized,
+        O: Output<W> + ?Sized,
+    {
+        let started = self.budget.time.map(|max| (Instant::now(), max));
+        let mut executed = 0;
+        let mut loops = LoopDetector::default();
+        // Tracing, profiling and loop detection need every step reported, and
+        // devices are left to the operations that know about them.
+        let lean = self.trace.is_none()
+            && self.profile.is_none()
+            && !self.budget.detect_loops
+            && self.devices.is_empty();
+        loop {
+            let out_of_instructions = self.budget.instructions.is_some_and(|max| executed >= max);
+            let out_of_time = executed % TIME_CHECK_INTERVAL == 0
+                && started.is_some_and(|(started, max)| started.elapsed() >= max);
+            if out_of_instructions || out_of_time {
+                return Ok(ProgramState::BudgetExhausted);
+            }
 
-                    if condition_satisfied {
-                        self.program_counter = rhs.try_into()?;
+            let outcome = match lean.then(|| self.step_lean(input, output)).flatten() {
+                Some(outcome) => outcome?,
+                None => {
+                    let step = self.step_with(input, output, false)?;
+                    if step.state.is_none() && self.budget.detect_loops && self.devices.is_empty() {
+                        loops
+                            .observe(self, &step)
+                            .map_err(|error| self.fault(error, self.program_counter, None))?;
+                    }
+                    Outcome {
+                        state: step.state,
+                        output: step.output.is_some(),
                     }
                 }
+            };
+            if let Some(state) = outcome.state {
+                return Ok(state);
+            }
+            executed += 1;
 
-                Opcode::Compare(comp) => {
-                    let lhs = self.fetch_parameter(&mut mode_flag)?;
-                    let rhs = self.fetch_parameter(&mut mode_flag)?;
-                    let target_pos = self.fetch_positional_parameter(&mut mode_flag)?;
+            if stop_on_output && outcome.output {
+                return Ok(ProgramState::ProducedOutput);
+            }
+        }
+    }
 
-                    let comparison_fulfilled = match comp {
-                        Comparison::LessThan => lhs < rhs,
-                        Comparison::Equals => lhs == rhs,
-                    };
+    /// Executes one built-in instruction from its decoded form without
+    /// reporting what it did. Returns `None`, having changed nothing, if the
+    /// instruction has to go through [`Program::step_with`] instead: because
+    /// it cannot be decoded ahead of time or is not a built-in one.
+    fn step_lean<I, O>(&mut self, input: &mut I, output: &mut O) -> Option<Result<Outcome, Fault>>
+    where
+        I: Input<W> + ?Sized,
+        O: Output<W> + ?Sized,
+    {
+        let pc = self.program_counter;
+        let cache::Cached {
+            instruction: Instruction { opcode, modes },
+            operands,
+        } = match &mut self.decode_cache {
+            Some(cache) => cache.get(&self.code, &self.dialect, pc)?,
+            None => cache::decode(&self.code, &self.dialect, pc)?,
+        };
+        if let Opcode::Custom(_) = opcode {
+            return None;
+        }
+        let mut machine = LeanMachine {
+            program: self,
+            input,
+            output,
+            pc,
+            modes,
+            operands,
+            read: 0,
+            address: None,
+            jump: None,
+            state: None,
+            printed: false,
+        };
+        let result = dialect::execute_builtin(opcode, &mut machine);
+        let LeanMachine {
+            address,
+            jump,
+            state,
+            printed,
+            ..
+        } = machine;
+        if let Err(error) = result {
+            return Some(Err(self.fault(error, pc, address)));
+        }
+        if state.is_none() {
+            self.program_counter = jump.unwrap_or(pc + 1 + opcode.parameter_count());
+            self.executed += 1;
+        }
+        Some(Ok(Outcome {
+            state,
+            output: printed,
+        }))
+    }
 
-                    *self.get_mut(target_pos)? = comparison_fulfilled.into();
-                }
+    /// Executes exactly one instruction and reports what it did.
+    ///
+    /// If the instruction is an input instruction and no input is available, or
+    /// if it is the exit instruction, the program counter is left pointing at it
+    /// and the returned step carries the corresponding [`ProgramState`]. The
+    /// same happens if the instruction faults.
+    pub fn step<I, O>(&mut self, input: &mut I, output: &mut O) -> Result<Step<W>, Fault>
+    where
+        I: Input<W> + ?Sized,
+        O: Output<W> + ?Sized,
+    {
+        self.step_with(input, output, true)
+    }
 
-                Opcode::Print => {
-                    let param = self.fetch_parameter(&mut mode_flag)?;
-                    output.push(param);
-                }
+    /// Executes one instruction. The parameters of the returned step are only
+    /// filled in if `detailed` is set or a trace or profile needs them, which
+    /// saves an allocation per instruction in [`Program::run`].
+    fn step_with<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        detailed: bool,
+    ) -> Result<Step<W>, Fault>
+    where
+        I: Input<W> + ?Sized,
+        O: Output<W> + ?Sized,
+    {
+        let pc = self.program_counter;
+        let mut operands = Operands {
+            pc,
+            modes: [0; 3],
+            count: 0,
+            read: 0,
+            cached: None,
+            detailed: detailed || self.trace.is_some() || self.profile.is_some(),
+            parameters: Vec::new(),
+            address: None,
+        };
+        let step = self
+            .execute_instruction(input, output, &mut operands)
+            .map_err(|error| {
+                self.program_counter = pc;
+                self.fault(error, pc, operands.address)
+            })?;
 
-                Opcode::Exit => return Ok((ProgramState::Exited, output)),
+        if step.state.is_none() {
+            self.executed += 1;
+            if let Some(trace) = &mut self.trace {
+                trace.entries.push((&step).into());
             }
         }
+        if step.state != Some(ProgramState::ExpectingInput) {
+            if let Some(profile) = &mut self.profile {
+                profile.record(&step);
+            }
+        }
+
+        Ok(step)
+    }
+
+    fn execute_instruction<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        operands: &mut Operands<W>,
+    ) -> Result<Step<W>, ExecutionError>
+    where
+        I: Input<W> + ?Sized,
+        O: Output<W> + ?Sized,
+    {
+        let pc = operands.pc;
+        let Instruction { opcode, modes } = self.fetch_instruction(operands)?;
+        operands.modes = modes;
+        operands.count = opcode.parameter_count();
+        let operation = self.dialect.operation(opcode.code())?;
+        let mut machine = Machine {
+            code: &mut self.code,
+            relative_base: &mut self.relative_base,
+            overflow_policy: self.overflow_policy,
+            input_mode: self.input_mode,
+            input: &mut DynInput(input),
+            output: &mut DynOutput(output),
+            devices: &self.devices,
+            clock: self.executed,
+            operands,
+            effects: Effects::default(),
+        };
+        operation.execute(&mut machine)?;
+        let effects = machine.effects;
+
+        self.program_counter = match effects.state {
+            Some(_) => pc,
+            None => effects.jump.unwrap_or(pc + 1 + operands.count),
+        };
+        Ok(Step {
+            pc,
+            opcode,
+            parameters: std::mem::take(&mut operands.parameters),
+            write: effects.write,
+            input: effects.input,
+            output: effects.output,
+            next_pc: self.program_counter,
+            state: effects.state,
+        })
+    }
+
+    /// Creates a program that starts executing at the beginning of `code`.
+    pub fn from_code(code: Vec<W>) -> Program<W> {
+        let mut program = Program {
+            code: code.into(),
+            program_counter: 0,
+            relative_base: 0,
+            trace: None,
+            profile: None,
+            budget: Budget::default(),
+            overflow_policy: OverflowPolicy::default(),
+            input_mode: InputMode::default(),
+            dialect: Dialect::standard(),
+            devices: Devices::new(),
+            decode_cache: None,
+            executed: 0,
+        };
+        program.set_decode_cache(true);
+        program
+    }
+
+    /// Chooses what happens when arithmetic on words or on the relative base
+    /// overflows. The default is [`OverflowPolicy::Checked`].
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
+    /// Chooses what happens when the program wants input that is not there.
+    /// The default is [`InputMode::Pause`].
+    pub fn set_input_mode(&mut self, mode: InputMode) {
+        self.input_mode = mode;
+    }
+
+    /// Chooses how memory is stored, moving everything already in it. The
+    /// default is [`memory::Backend::Hybrid`].
+    pub fn set_memory_backend(&mut self, backend: memory::Backend) {
+        self.code.set_backend(backend);
+    }
+
+    /// Chooses whether decoded instructions are kept around so that they do
+    /// not have to be decoded again every time they are executed. Enabling
+    /// the cache decodes the reachable code up front. The cache is on by
+    /// default and only changes how fast the program runs.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled.then(|| cache::DecodeCache::new(&self.code, &self.dialect));
+    }
+
+    /// Chooses the instruction set the program is executed with. The default
+    /// is [`Dialect::standard`].
+    pub fn set_dialect(&mut self, dialect: Dialect<W>) {
+        self.dialect = dialect;
+        if self.decode_cache.is_some() {
+            self.set_decode_cache(true);
+        }
+    }
+
+    pub fn dialect(&self) -> &Dialect<W> {
+        &self.dialect
+    }
+
+    /// Maps `device` to the addresses in `range` and returns a handle to it.
+    /// Clones of the program share the device.
+    pub fn attach<D>(
+        &mut self,
+        range: Range<usize>,
+        device: D,
+    ) -> Result<Arc<Mutex<D>>, DeviceError>
+    where
+        D: Device<W> + 'static,
+    {
+        let device = Arc::new(Mutex::new(device));
+        self.devices.attach(range, device.clone())?;
+        Ok(device)
+    }
+
+    /// Limits how much work each subsequent call to [`Program::run`] or
+    /// [`Program::execute`] may do.
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+    }
+
+    /// Starts recording every executed instruction, discarding any trace
+    /// recorded so far.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(trace::Trace::default());
+    }
+
+    /// Stops recording and returns what was recorded.
+    pub fn take_trace(&mut self) -> Option<trace::Trace<W>> {
+        self.trace.take()
+    }
+
+    /// Starts counting executions, memory accesses and jumps, discarding any
+    /// profile collected so far.
+    pub fn start_profile(&mut self) {
+        self.profile = Some(profile::Profile::default());
+    }
+
+    /// Stops profiling and returns the counts collected.
+    pub fn take_profile(&mut self) -> Option<profile::Profile> {
+        self.profile.take()
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
     }
 
-    fn fetch_positional_parameter(&mut self, mode_flag: &mut i64) -> Result<usize, ExecutionError> {
-        let mode = Program::read_next_parameter_mode(mode_flag)?;
-        match mode {
-            ParameterMode::Immediate => Err(ExecutionError::InvalidImmediateParameter),
-            ParameterMode::Position => self.fetch_position(),
+    pub fn memory(&self) -> &Memory<W> {
+        &self.code
+    }
+
+    /// The number of instructions executed since the program was created.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    fn fault(&self, error: ExecutionError, pc: usize, address: Option<i64>) -> Fault {
+        let instruction = self.code.get(pc).and_then(W::to_i64);
+        let opcode = instruction
+            .and_then(|word| self.dialect.parse_instruction(word).ok())
+            .map(|instruction| instruction.opcode);
+        let image: Vec<i64> = self
+            .code
+            .iter()
+            .take(self.code.dense_len())
+            .map_while(W::to_i64)
+            .collect();
+        Fault {
+            error,
+            pc,
+            instruction,
+            opcode,
+            address,
+            executed: self.executed,
+            window: disasm::window(&image, &self.dialect, pc, FAULT_CONTEXT_LINES),
         }
     }
 
-    fn fetch_parameter(&mut self, mode_flag: &mut i64) -> Result<i64, ExecutionError> {
-        let mode = Program::read_next_parameter_mode(mode_flag)?;
-        match mode {
-            ParameterMode::Immediate => self.fetch_operand(),
-            ParameterMode::Position => {
-                let p = self.fetch_position()?;
-                self.get(p)
+    fn fetch_instruction(
+        &mut self,
+        operands: &mut Operands<W>,
+    ) -> Result<Instruction, ExecutionError> {
+        if let Some(cache) = &mut self.decode_cache {
+            if let Some(cached) = cache.get(&self.code, &self.dialect, operands.pc) {
+                operands.cached = Some(cached.operands);
+                return Ok(cached.instruction);
             }
         }
+        let value = self
+            .code
+            .get(operands.pc)
+            .cloned()
+            .unwrap_or_else(|| W::from(0));
+        self.dialect.parse_instruction(narrow(&value)?)
     }
+}
 
-    fn read_next_parameter_mode(mode_flag: &mut i64) -> Result<ParameterMode, ExecutionError> {
-        let mode = *mode_flag % 10;
-        *mode_flag /= 10;
-        match mode {
-            0 => Ok(ParameterMode::Position),
-            1 => Ok(ParameterMode::Immediate),
-            _ => Err(ExecutionError::UnknownParameterMode(mode)),
+/// Clones share the decode cache as well as memory.
+impl<W: Word> Clone for Program<W> {
+    fn clone(&self) -> Program<W> {
+        let code = self.code.clone();
+        let decode_cache = self
+            .decode_cache
+            .as_ref()
+            .map(|cache| cache.for_clone(&self.code, &code));
+        Program {
+            code,
+            program_counter: self.program_counter,
+            relative_base: self.relative_base,
+            trace: self.trace.clone(),
+            profile: self.profile.clone(),
+            budget: self.budget,
+            overflow_policy: self.overflow_policy,
+            input_mode: self.input_mode,
+            dialect: self.dialect.clone(),
+            devices: self.devices.clone(),
+            decode_cache,
+            executed: self.executed,
         }
     }
+}
+
+/// Converts a word that is used as an instruction, address or offset.
+fn narrow<W: Word>(word: &W) -> Result<i64, ExecutionError> {
+    word.to_i64().ok_or(ExecutionError::WordOutOfRange)
+}
 
-    fn fetch_instruction(&mut self) -> Result<Instruction, ExecutionError> {
-        let value = self.fetch_operand()?;
-        let opcode = Program::parse_opcode(value % 100)?;
-        let mode_flag = value / 100;
-        Ok(Instruction { opcode, mode_flag })
+/// Instruction decoding only ever looks at machine-sized words.
+impl Program {
+    fn parameter_mode(digit: u8) -> Result<ParameterMode, ExecutionError> {
+        match digit {
+            0 => Ok(ParameterMode::Position),
+            1 => Ok(ParameterMode::Immediate),
+            2 => Ok(ParameterMode::Relative),
+            _ => Err(ExecutionError::UnknownParameterMode(digit.into())),
+        }
     }
 
     fn parse_opcode(opcode: i64) -> Result<Opcode, ExecutionError> {
@@ -127,68 +504,483 @@ impl Program {
             6 => Ok(Opcode::Jump(JumpCondition::False)),
             7 => Ok(Opcode::Compare(Comparison::LessThan)),
             8 => Ok(Opcode::Compare(Comparison::Equals)),
+            9 => Ok(Opcode::AdjustRelativeBase),
             99 => Ok(Opcode::Exit),
             _ => Err(ExecutionError::UnknownOpcode(opcode)),
         }
     }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgramState {
+    ExpectingInput,
+    Exited,
+    /// The run was stopped by its [`Budget`]; running again continues where it
+    /// left off.
+    BudgetExhausted,
+    /// Returned by [`Program::run_until_output`] after the program wrote a
+    /// value; running again continues with the next instruction.
+    ProducedOutput,
+}
 
-    fn fetch_position(&mut self) -> Result<usize, ExecutionError> {
-        Ok(self.fetch_operand()?.try_into()?)
+/// Limits on a single call to [`Program::run`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Budget {
+    /// The maximum number of instructions to execute.
+    pub instructions: Option<u64>,
+    /// The maximum wall-clock time to run for.
+    pub time: Option<Duration>,
+    /// Fail with [`ExecutionError::InfiniteLoop`] as soon as the program is seen
+    /// to return to an earlier state without having read any input since.
+    /// Ignored while devices are attached, as their state is not compared.
+    pub detect_loops: bool,
+}
+
+/// How many instructions to execute between checks of the clock.
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// Finds repeated machine states at jump instructions using Brent's cycle
+/// detection, so only a single earlier state has to be kept around.
+struct LoopDetector<W> {
+    saved: Option<(usize, i64, Memory<W>)>,
+    power: u64,
+    distance: u64,
+}
+
+impl<W> Default for LoopDetector<W> {
+    fn default() -> LoopDetector<W> {
+        LoopDetector {
+            saved: None,
+            power: 0,
+            distance: 0,
+        }
     }
+}
+
+impl<W: Word> LoopDetector<W> {
+    fn observe(&mut self, program: &Program<W>, step: &Step<W>) -> Result<(), ExecutionError> {
+        if step.input.is_some() {
+            *self = LoopDetector::default();
+            return Ok(());
+        }
+        if !matches!(step.opcode, Opcode::Jump(_)) {
+            return Ok(());
+        }
+
+        if let Some((pc, relative_base, code)) = &self.saved {
+            if *pc == program.program_counter
+                && *relative_base == program.relative_base
+                && *code == program.code
+            {
+                return Err(ExecutionError::InfiniteLoop { pc: *pc });
+            }
+        }
 
-    fn fetch_operand(&mut self) -> Result<i64, ExecutionError> {
-        let result = self.get(self.program_counter)?;
-        self.program_counter += 1;
-        Ok(result)
+        self.distance += 1;
+        if self.distance >= self.power {
+            self.saved = Some((
+                program.program_counter,
+                program.relative_base,
+                program.code.clone(),
+            ));
+            self.power = (self.power * 2).max(1);
+            self.distance = 0;
+        }
+        Ok(())
     }
+}
 
-    fn get_mut(&mut self, pos: usize) -> Result<&mut i64, ExecutionError> {
-        self.code.get_mut(pos).ok_or(ExecutionError::OutOfBounds)
+/// What [`Program::run_while`] needs to know about an executed instruction.
+struct Outcome {
+    state: Option<ProgramState>,
+    output: bool,
+}
+
+/// Executes a built-in instruction for [`Program::step_lean`], taking its
+/// parameters from the decoded instruction and recording none of its effects
+/// besides those the interpreter needs.
+struct LeanMachine<'a, W: Word, I: ?Sized, O: ?Sized> {
+    program: &'a mut Program<W>,
+    input: &'a mut I,
+    output: &'a mut O,
+    pc: usize,
+    modes: [u8; 3],
+    operands: [i64; 3],
+    /// The number of parameters fetched so far.
+    read: usize,
+    /// The most recently resolved memory address or jump target.
+    address: Option<i64>,
+    jump: Option<usize>,
+    state: Option<ProgramState>,
+    printed: bool,
+}
+
+impl<W: Word, I: ?Sized, O: ?Sized> LeanMachine<'_, W, I, O> {
+    /// Fetches the mode and raw word of the next parameter.
+    fn next_parameter(&mut self) -> Result<(ParameterMode, i64), ExecutionError> {
+        let index = self.read;
+        let raw = *self
+            .operands
+            .get(index)
+            .ok_or(ExecutionError::TooManyParameters { pc: self.pc })?;
+        self.read += 1;
+        Ok((Program::parameter_mode(self.modes[index])?, raw))
     }
 
-    fn get(&self, pos: usize) -> Result<i64, ExecutionError> {
-        Ok(*self.code.get(pos).ok_or(ExecutionError::OutOfBounds)?)
+    /// Resolves a position or relative mode parameter like
+    /// [`Machine::target`] does.
+    fn effective_address(
+        &mut self,
+        mode: ParameterMode,
+        raw: i64,
+    ) -> Result<usize, ExecutionError> {
+        let address = match mode {
+            ParameterMode::Relative => self
+                .program
+                .overflow_policy
+                .add(self.program.relative_base, raw)
+                .ok_or(ExecutionError::Overflow {
+                    pc: self.pc,
+                    op: OverflowingOperation::RelativeAddress,
+                })?,
+            _ => raw,
+        };
+        self.address = Some(address);
+        usize::try_from(address).map_err(|_| ExecutionError::OutOfBounds)
     }
 }
 
-#[derive(PartialEq, Eq)]
-pub enum ProgramState {
-    ExpectingInput,
-    Exited,
+impl<W, I, O> dialect::BuiltinMachine<W> for LeanMachine<'_, W, I, O>
+where
+    W: Word,
+    I: Input<W> + ?Sized,
+    O: Output<W> + ?Sized,
+{
+    fn pc(&self) -> usize {
+        self.pc
+    }
+
+    fn overflow_policy(&self) -> OverflowPolicy {
+        self.program.overflow_policy
+    }
+
+    fn read(&mut self) -> Result<W, ExecutionError> {
+        let (mode, raw) = self.next_parameter()?;
+        if mode == ParameterMode::Immediate {
+            return Ok(W::from(raw));
+        }
+        let address = self.effective_address(mode, raw)?;
+        Ok(self
+            .program
+            .code
+            .get(address)
+            .cloned()
+            .unwrap_or_else(|| W::from(0)))
+    }
+
+    fn target(&mut self) -> Result<usize, ExecutionError> {
+        let (mode, raw) = self.next_parameter()?;
+        if mode == ParameterMode::Immediate {
+            return Err(ExecutionError::InvalidImmediateParameter);
+        }
+        self.effective_address(mode, raw)
+    }
+
+    /// Writes to memory, dropping any decoded instruction it overwrites.
+    fn write(&mut self, address: usize, value: W) -> Result<(), ExecutionError> {
+        let program = &mut *self.program;
+        *program.code.word_mut(address) = value;
+        if let Some(cache) = &mut program.decode_cache {
+            cache.written(&program.code, address);
+        }
+        Ok(())
+    }
+
+    fn input(&mut self) -> Result<Option<W>, ExecutionError> {
+        match self.input.read() {
+            Some(value) => Ok(Some(value)),
+            None if self.program.input_mode == InputMode::Strict => {
+                Err(ExecutionError::UnexpectedEndOfInput { pc: self.pc })
+            }
+            None => {
+                self.state = Some(ProgramState::ExpectingInput);
+                Ok(None)
+            }
+        }
+    }
+
+    fn output(&mut self, value: W) {
+        self.output.write(value);
+        self.printed = true;
+    }
+
+    fn jump(&mut self, target: W) -> Result<(), ExecutionError> {
+        let target = narrow(&target)?;
+        self.address = Some(target);
+        self.jump = Some(usize::try_from(target).map_err(|_| ExecutionError::OutOfBounds)?);
+        Ok(())
+    }
+
+    fn adjust_relative_base(&mut self, offset: W) -> Result<(), ExecutionError> {
+        let offset = narrow(&offset)?;
+        self.program.relative_base = self
+            .program
+            .overflow_policy
+            .add(self.program.relative_base, offset)
+            .ok_or(ExecutionError::Overflow {
+                pc: self.pc,
+                op: OverflowingOperation::AdjustRelativeBase,
+            })?;
+        Ok(())
+    }
+
+    fn exit(&mut self) {
+        self.state = Some(ProgramState::Exited);
+    }
+}
+
+/// Everything that happened while executing a single instruction.
+#[derive(Clone, Debug)]
+pub struct Step<W = i64> {
+    /// Address of the executed instruction.
+    pub pc: usize,
+    pub opcode: Opcode,
+    pub parameters: Vec<Parameter<W>>,
+    pub write: Option<MemoryWrite<W>>,
+    pub input: Option<W>,
+    pub output: Option<W>,
+    pub next_pc: usize,
+    /// Set if the program exited or is waiting for input; the instruction then
+    /// had no effect and `next_pc` equals `pc`.
+    pub state: Option<ProgramState>,
+}
+
+/// A decoded instruction parameter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parameter<W = i64> {
+    pub mode: ParameterMode,
+    /// The word stored in the instruction stream.
+    pub raw: W,
+    /// The memory address the parameter refers to, unless it is immediate.
+    pub address: Option<usize>,
+    /// The value that was read, or `None` if the parameter is a write target.
+    pub value: Option<W>,
 }
 
-enum ParameterMode {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryWrite<W = i64> {
+    pub address: usize,
+    pub value: W,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParameterMode {
     Position,
     Immediate,
+    Relative,
 }
 
-enum ArithmeticOperation {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ArithmeticOperation {
     Add,
     Mul,
 }
 
-enum JumpCondition {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum JumpCondition {
     True,
     False,
 }
 
-enum Comparison {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Comparison {
     LessThan,
     Equals,
 }
 
-enum Opcode {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Opcode {
     Arithmetic(ArithmeticOperation),
     Store,
     Print,
     Jump(JumpCondition),
     Compare(Comparison),
+    AdjustRelativeBase,
     Exit,
+    /// An opcode registered with a [`Dialect`].
+    Custom(dialect::CustomOpcode),
 }
 
+impl Opcode {
+    /// The numeric opcode, as understood by `parse_opcode` or the dialect the
+    /// opcode was registered with.
+    pub fn code(self) -> i64 {
+        match self {
+            Opcode::Arithmetic(ArithmeticOperation::Add) => 1,
+            Opcode::Arithmetic(ArithmeticOperation::Mul) => 2,
+            Opcode::Store => 3,
+            Opcode::Print => 4,
+            Opcode::Jump(JumpCondition::True) => 5,
+            Opcode::Jump(JumpCondition::False) => 6,
+            Opcode::Compare(Comparison::LessThan) => 7,
+            Opcode::Compare(Comparison::Equals) => 8,
+            Opcode::AdjustRelativeBase => 9,
+            Opcode::Exit => 99,
+            Opcode::Custom(custom) => custom.code.into(),
+        }
+    }
+
+    /// The number of parameters following the instruction word.
+    pub fn parameter_count(self) -> usize {
+        match self {
+            Opcode::Arithmetic(_) | Opcode::Compare(_) => 3,
+            Opcode::Jump(_) => 2,
+            Opcode::Store | Opcode::Print | Opcode::AdjustRelativeBase => 1,
+            Opcode::Exit => 0,
+            Opcode::Custom(custom) => custom.parameter_count(),
+        }
+    }
+
+    /// The index of the parameter the instruction writes to, if any. Custom
+    /// opcodes may write to more than one; this is the first of them.
+    pub fn target_parameter(self) -> Option<usize> {
+        match self {
+            Opcode::Arithmetic(_) | Opcode::Compare(_) => Some(2),
+            Opcode::Store => Some(0),
+            Opcode::Print | Opcode::Jump(_) | Opcode::AdjustRelativeBase | Opcode::Exit => None,
+            Opcode::Custom(custom) => {
+                (0..custom.parameter_count()).find(|&parameter| custom.is_target(parameter))
+            }
+        }
+    }
+
+    /// Whether the instruction writes to the parameter with the given index.
+    pub fn is_target(self, parameter: usize) -> bool {
+        match self {
+            Opcode::Custom(custom) => custom.is_target(parameter),
+            _ => self.target_parameter() == Some(parameter),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 struct Instruction {
     opcode: Opcode,
-    mode_flag: i64,
+    /// The mode digit of every parameter, first parameter first.
+    modes: [u8; 3],
+}
+
+impl Instruction {
+    /// Takes the mode digits from the instruction word `value`. Invalid mode
+    /// digits are only reported once the parameter is used.
+    fn new(opcode: Opcode, value: i64) -> Instruction {
+        let mut mode_flag = value / 100;
+        let mut modes = [0; 3];
+        for mode in &mut modes {
+            *mode = (mode_flag % 10) as u8;
+            mode_flag /= 10;
+        }
+        Instruction { opcode, modes }
+    }
+}
+
+/// The parameters of the instruction being executed, as far as they have been
+/// decoded.
+struct Operands<W> {
+    pc: usize,
+    modes: [u8; 3],
+    /// The number of parameters the instruction has.
+    count: usize,
+    /// The number of parameters fetched so far.
+    read: usize,
+    /// The raw parameter words, if the instruction was found in the decode
+    /// cache.
+    cached: Option<[i64; 3]>,
+    /// Whether to record every parameter for the [`Step`].
+    detailed: bool,
+    parameters: Vec<Parameter<W>>,
+    /// The most recently resolved memory address or jump target.
+    address: Option<i64>,
+}
+
+impl<W> Operands<W> {
+    fn next_mode(&mut self) -> Result<ParameterMode, ExecutionError> {
+        if self.read >= self.count {
+            return Err(ExecutionError::TooManyParameters { pc: self.pc });
+        }
+        let digit = self.modes[self.read];
+        self.read += 1;
+        Program::parameter_mode(digit)
+    }
+}
+
+/// What to do when an input instruction finds no input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputMode {
+    /// Stop with [`ProgramState::ExpectingInput`], so that running again
+    /// retries the instruction.
+    #[default]
+    Pause,
+    /// Fail with [`ExecutionError::UnexpectedEndOfInput`], for batch runs
+    /// that are given all of their input up front.
+    Strict,
+}
+
+/// What to do when word arithmetic does not fit into an `i64`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Fail with [`ExecutionError::Overflow`].
+    #[default]
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+impl OverflowPolicy {
+    fn add(self, lhs: i64, rhs: i64) -> Option<i64> {
+        match self {
+            OverflowPolicy::Checked => lhs.checked_add(rhs),
+            OverflowPolicy::Wrapping => Some(lhs.wrapping_add(rhs)),
+            OverflowPolicy::Saturating => Some(lhs.saturating_add(rhs)),
+        }
+    }
+
+    fn mul(self, lhs: i64, rhs: i64) -> Option<i64> {
+        match self {
+            OverflowPolicy::Checked => lhs.checked_mul(rhs),
+            OverflowPolicy::Wrapping => Some(lhs.wrapping_mul(rhs)),
+            OverflowPolicy::Saturating => Some(lhs.saturating_mul(rhs)),
+        }
+    }
+}
+
+/// The computation that overflowed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowingOperation {
+    Add,
+    Mul,
+    AdjustRelativeBase,
+    RelativeAddress,
+}
+
+impl From<ArithmeticOperation> for OverflowingOperation {
+    fn from(op: ArithmeticOperation) -> OverflowingOperation {
+        match op {
+            ArithmeticOperation::Add => OverflowingOperation::Add,
+            ArithmeticOperation::Mul => OverflowingOperation::Mul,
+        }
+    }
+}
+
+impl fmt::Display for OverflowingOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverflowingOperation::Add => write!(f, "addition"),
+            OverflowingOperation::Mul => write!(f, "multiplication"),
+            OverflowingOperation::AdjustRelativeBase => write!(f, "relative base adjustment"),
+            OverflowingOperation::RelativeAddress => write!(f, "relative address computation"),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -196,7 +988,7 @@ pub enum Error {
     #[error(transparent)]
     Parse(#[from] ParseError),
     #[error(transparent)]
-    Execution(#[from] ExecutionError),
+    Execution(#[from] Fault),
 }
 
 #[derive(Error, Debug)]
@@ -205,30 +997,114 @@ pub enum ExecutionError {
     UnknownOpcode(i64),
     #[error("Attempted to read or write out of bounds")]
     OutOfBounds,
-    #[error("Attempted to read some nonexistent input")]
-    UnexpectedEndOfInput,
+    #[error("Attempted to read some nonexistent input at {pc}")]
+    UnexpectedEndOfInput { pc: usize },
     #[error("Unknown parameter mode {0}")]
     UnknownParameterMode(i64),
     #[error("Received immediate mode parameter in an invalid position")]
     InvalidImmediateParameter,
     #[error("Conversion from int failed")]
     FromInt(#[from] TryFromIntError),
+    #[error("Program is stuck in an infinite loop at {pc}")]
+    InfiniteLoop { pc: usize },
+    #[error("Overflow in {op} at {pc}")]
+    Overflow { pc: usize, op: OverflowingOperation },
+    #[error("Word is too large to be used as an instruction, address or offset")]
+    WordOutOfRange,
+    /// An operation registered with a [`Dialect`] fetched more parameters
+    /// than it declared.
+    #[error("Instruction at {pc} fetched more parameters than it has")]
+    TooManyParameters { pc: usize },
+    /// Reported by an operation registered with a [`Dialect`].
+    #[error("{0}")]
+    Custom(String),
+}
+
+/// How many disassembled lines a [`Fault`] shows on either side of the
+/// faulting instruction.
+const FAULT_CONTEXT_LINES: usize = 3;
+
+/// An [`ExecutionError`] together with where and when it happened.
+#[derive(Debug)]
+pub struct Fault {
+    pub error: ExecutionError,
+    /// Address of the faulting instruction.
+    pub pc: usize,
+    /// The instruction word, unless it does not fit into an `i64`.
+    pub instruction: Option<i64>,
+    /// The opcode, if the instruction word could be decoded.
+    pub opcode: Option<Opcode>,
+    /// The memory address or jump target the instruction was using when it
+    /// faulted, which may be negative.
+    pub address: Option<i64>,
+    /// The number of instructions executed before the faulting one.
+    pub executed: u64,
+    /// The disassembly listing around the faulting instruction.
+    pub window: Vec<disasm::Line>,
+}
+
+/// The display already includes [`Fault::error`], so it is not reported as
+/// the source as well; error reporters would show its message twice.
+impl std::error::Error for Fault {}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (pc {}", self.error, self.pc)?;
+        if let Some(word) = self.instruction {
+            write!(f, ", instruction {word}")?;
+        }
+        if let Some(opcode) = self.opcode {
+            write!(f, " `{}`", opcode.mnemonic())?;
+        }
+        if let Some(address) = self.address {
+            write!(f, ", address {address}")?;
+        }
+        write!(f, ", after {} instructions)", self.executed)?;
+        for line in &self.window {
+            let start = line.address();
+            let marker = if (start..start + line.words().len()).contains(&self.pc) {
+                "=>"
+            } else {
+                "  "
+            };
+            write!(f, "\n{marker} {line}")?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum ParseError {
     #[error(transparent)]
     Number(#[from] ParseIntError),
+    #[error("Invalid word `{0}`")]
+    InvalidWord(String),
 }
 
-impl FromStr for Program {
+impl<W: Word> FromStr for Program<W> {
     type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let code: Result<_, _> = s.split(',').map(str::parse).collect();
-        Ok(Program {
-            code: code?,
-            program_counter: 0,
-        })
+        let code: Result<_, _> = s.split(',').map(W::parse_word).collect();
+        Ok(Program::from_code(code?))
+    }
+}
+
+/// Shows the words of memory separated by commas. Words past the dense region
+/// are shown as `address:word`, and only if they are stored, so that a
+/// program that wrote to a huge address is not shown with every zero below it.
+impl<W: Word> fmt::Display for Program<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dense_len = self.code.dense_len();
+        for (i, (address, word)) in self.code.stored_words().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            if address >= dense_len {
+                write!(f, "{address}:")?;
+            }
+            write!(f, "{word}")?;
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,106 @@
+//! Talking to programs that read and print ASCII text.
+//!
+//! Such programs read newline-terminated commands one character code at a
+//! time and print prompts the same way. Values outside the ASCII range are
+//! usually the answer being computed, so they are kept apart from the text.
+
+use std::{collections::VecDeque, mem};
+
+use super::{Fault, Output, Program, ProgramState};
+
+/// Turns a line into the character codes a program reads, including the
+/// terminating newline.
+pub fn encode_line(line: &str) -> impl Iterator<Item = i64> + '_ {
+    line.chars().chain(['\n']).map(|c| i64::from(u32::from(c)))
+}
+
+/// An [`Output`] that assembles ASCII codes into lines and sets aside every
+/// other value.
+#[derive(Clone, Debug, Default)]
+pub struct Decoder {
+    partial: String,
+    lines: VecDeque<String>,
+    values: Vec<i64>,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Removes and returns every complete line, without the newline.
+    pub fn take_lines(&mut self) -> Vec<String> {
+        self.lines.drain(..).collect()
+    }
+
+    /// Removes and returns every value that was not an ASCII code.
+    pub fn take_values(&mut self) -> Vec<i64> {
+        mem::take(&mut self.values)
+    }
+
+    /// Text printed since the last newline, such as a prompt.
+    pub fn partial_line(&self) -> &str {
+        &self.partial
+    }
+
+    /// Removes and returns the text printed since the last newline.
+    pub fn take_partial_line(&mut self) -> String {
+        mem::take(&mut self.partial)
+    }
+}
+
+impl Output for Decoder {
+    fn write(&mut self, value: i64) {
+        match u8::try_from(value) {
+            Ok(b'\n') => self.lines.push_back(mem::take(&mut self.partial)),
+            Ok(byte) if byte.is_ascii() => self.partial.push(char::from(byte)),
+            _ => self.values.push(value),
+        }
+    }
+}
+
+/// A program together with the text going in and out of it.
+#[derive(Clone)]
+pub struct Ascii {
+    program: Program,
+    input: VecDeque<i64>,
+    output: Decoder,
+}
+
+impl Ascii {
+    pub fn new(program: Program) -> Ascii {
+        Ascii {
+            program,
+            input: VecDeque::new(),
+            output: Decoder::new(),
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Queues `line` followed by a newline as input.
+    pub fn send_line(&mut self, line: &str) {
+        self.input.extend(encode_line(line));
+    }
+
+    /// Runs until the program exits or has read every queued line.
+    pub fn run(&mut self) -> Result<ProgramState, Fault> {
+        self.program.run(&mut self.input, &mut self.output)
+    }
+
+    /// Removes and returns the text printed since the last newline, which
+    /// is typically a prompt.
+    pub fn take_partial_line(&mut self) -> String {
+        self.output.take_partial_line()
+    }
+
+    pub fn take_lines(&mut self) -> Vec<String> {
+        self.output.take_lines()
+    }
+
+    pub fn take_values(&mut self) -> Vec<i64> {
+        self.output.take_values()
+    }
+}
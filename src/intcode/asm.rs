@@ -0,0 +1,256 @@
+//! A small assembly language for Intcode.
+//!
+//! Every line holds an optional `label:`, followed by an optional instruction
+//! or `.data` directive, followed by an optional `;` comment:
+//!
+//! ```text
+//! loop:   in [x]              ; read a number
+//!         mul [x], #2, [x]
+//!         out [x]
+//!         jnz #1, #loop
+//! x:      .data 0
+//! ```
+//!
+//! Operands are written as `[address]` for position mode, `#value` for
+//! immediate mode and `[rb+offset]` for relative mode. Wherever a number is
+//! expected, a label (optionally followed by `+n` or `-n`) may be used instead.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use super::{ParameterMode, Program};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AsmError {
+    #[error("Line {line}: unknown mnemonic `{mnemonic}`")]
+    UnknownMnemonic { line: usize, mnemonic: String },
+    #[error("Line {line}: expected {expected} operands, found {found}")]
+    OperandCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Line {line}: invalid operand `{operand}`")]
+    InvalidOperand { line: usize, operand: String },
+    #[error("Line {line}: the written operand cannot be immediate")]
+    ImmediateTarget { line: usize },
+    #[error("Line {line}: invalid label `{label}`")]
+    InvalidLabel { line: usize, label: String },
+    #[error("Line {line}: label `{label}` is already defined")]
+    DuplicateLabel { line: usize, label: String },
+    #[error("Line {line}: undefined label `{label}`")]
+    UndefinedLabel { line: usize, label: String },
+}
+
+/// A number or a reference to a label plus an offset.
+enum Value<'a> {
+    Number(i64),
+    Label(&'a str, i64),
+}
+
+enum Item<'a> {
+    Instruction {
+        opcode: i64,
+        operands: Vec<(ParameterMode, Value<'a>)>,
+    },
+    Data(Vec<Value<'a>>),
+}
+
+impl Item<'_> {
+    fn word_count(&self) -> usize {
+        match self {
+            Item::Instruction { operands, .. } => operands.len() + 1,
+            Item::Data(values) => values.len(),
+        }
+    }
+}
+
+/// Translates assembly source into Intcode.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut items = Vec::new();
+    let mut address = 0;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut rest = text.split(';').next().unwrap_or("").trim();
+
+        if let Some((label, tail)) = rest.split_once(':') {
+            let label = label.trim();
+            if !is_identifier(label) {
+                return Err(AsmError::InvalidLabel {
+                    line,
+                    label: label.to_owned(),
+                });
+            }
+            if labels.insert(label, address).is_some() {
+                return Err(AsmError::DuplicateLabel {
+                    line,
+                    label: label.to_owned(),
+                });
+            }
+            rest = tail.trim();
+        }
+
+        if rest.is_empty() {
+            continue;
+        }
+
+        let item = parse_item(line, rest)?;
+        address += item.word_count();
+        items.push((line, item));
+    }
+
+    let resolve = |line: usize, value: &Value| match *value {
+        Value::Number(n) => Ok(n),
+        Value::Label(label, offset) => labels
+            .get(label)
+            .map(|&address| address as i64 + offset)
+            .ok_or_else(|| AsmError::UndefinedLabel {
+                line,
+                label: label.to_owned(),
+            }),
+    };
+
+    let mut code = Vec::with_capacity(address);
+    for (line, item) in &items {
+        match item {
+            Item::Instruction { opcode, operands } => {
+                let mut word = *opcode;
+                let mut factor = 100;
+                for (mode, _) in operands {
+                    word += factor * mode_flag(*mode);
+                    factor *= 10;
+                }
+                code.push(word);
+                for (_, value) in operands {
+                    code.push(resolve(*line, value)?);
+                }
+            }
+            Item::Data(values) => {
+                for value in values {
+                    code.push(resolve(*line, value)?);
+                }
+            }
+        }
+    }
+
+    Ok(code)
+}
+
+/// Like [`assemble`], but produces a ready-to-run program.
+pub fn assemble_program(source: &str) -> Result<Program, AsmError> {
+    Ok(Program::from_code(assemble(source)?))
+}
+
+fn parse_item(line: usize, text: &str) -> Result<Item<'_>, AsmError> {
+    let (mnemonic, args) = match text.split_once(char::is_whitespace) {
+        Some((mnemonic, args)) => (mnemonic, args.trim()),
+        None => (text, ""),
+    };
+    let args: Vec<&str> = if args.is_empty() {
+        Vec::new()
+    } else {
+        args.split(',').map(str::trim).collect()
+    };
+
+    if mnemonic == ".data" {
+        let values = args
+            .iter()
+            .map(|arg| parse_value(arg).ok_or_else(|| invalid_operand(line, arg)))
+            .collect::<Result<_, _>>()?;
+        return Ok(Item::Data(values));
+    }
+
+    let opcode = (1..=99)
+        .filter_map(|code| Program::parse_opcode(code).ok())
+        .find(|opcode| opcode.mnemonic() == mnemonic)
+        .ok_or_else(|| AsmError::UnknownMnemonic {
+            line,
+            mnemonic: mnemonic.to_owned(),
+        })?;
+
+    if args.len() != opcode.parameter_count() {
+        return Err(AsmError::OperandCount {
+            line,
+            expected: opcode.parameter_count(),
+            found: args.len(),
+        });
+    }
+
+    let mut operands = Vec::with_capacity(args.len());
+    for (i, arg) in args.iter().enumerate() {
+        let operand = parse_operand(arg).ok_or_else(|| invalid_operand(line, arg))?;
+        if operand.0 == ParameterMode::Immediate && opcode.is_target(i) {
+            return Err(AsmError::ImmediateTarget { line });
+        }
+        operands.push(operand);
+    }
+
+    Ok(Item::Instruction {
+        opcode: opcode.code(),
+        operands,
+    })
+}
+
+fn parse_operand(text: &str) -> Option<(ParameterMode, Value<'_>)> {
+    if let Some(value) = text.strip_prefix('#') {
+        return Some((ParameterMode::Immediate, parse_value(value.trim())?));
+    }
+
+    let inner = text.strip_prefix('[')?.strip_suffix(']')?.trim();
+    let compact: String = inner.split_whitespace().collect();
+    match compact.strip_prefix("rb") {
+        Some("") => Some((ParameterMode::Relative, Value::Number(0))),
+        Some(offset) if offset.starts_with(['+', '-']) => {
+            let offset = offset.strip_prefix('+').unwrap_or(offset).parse().ok()?;
+            Some((ParameterMode::Relative, Value::Number(offset)))
+        }
+        _ => Some((ParameterMode::Position, parse_value(inner)?)),
+    }
+}
+
+fn parse_value(text: &str) -> Option<Value<'_>> {
+    if let Ok(n) = text.parse() {
+        return Some(Value::Number(n));
+    }
+
+    let split = text.find(['+', '-']).unwrap_or(text.len());
+    let (label, offset) = text.split_at(split);
+    let label = label.trim();
+    if !is_identifier(label) {
+        return None;
+    }
+    let offset = offset.replace(char::is_whitespace, "");
+    let offset = match offset.strip_prefix('+') {
+        _ if offset.is_empty() => 0,
+        Some(positive) => positive.parse().ok()?,
+        None => offset.parse().ok()?,
+    };
+    Some(Value::Label(label, offset))
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && text != "rb"
+}
+
+fn mode_flag(mode: ParameterMode) -> i64 {
+    match mode {
+        ParameterMode::Position => 0,
+        ParameterMode::Immediate => 1,
+        ParameterMode::Relative => 2,
+    }
+}
+
+fn invalid_operand(line: usize, operand: &str) -> AsmError {
+    AsmError::InvalidOperand {
+        line,
+        operand: operand.to_owned(),
+    }
+}
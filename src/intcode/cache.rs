@@ -0,0 +1,182 @@
+//! Instructions decoded ahead of time.
+//!
+//! An entry holds everything that can be known about an instruction without
+//! running it: the opcode, the parameter mode digits and the raw operand
+//! words, so that executing it needs no decoding and no reads besides those
+//! of its position and relative mode parameters. An entry is only valid as
+//! long as none of those words change. The interpreter reports its own writes
+//! with [`DecodeCache::written`], which drops the entries covering the
+//! written address. Every other change, such as a write through
+//! [`super::Program::code`] or by a custom operation, is found by following
+//! the [`Memory::version`] of the program's memory: the entries covering the
+//! addresses written since are dropped, and if those addresses are not known
+//! any more, every entry is checked against memory again.
+
+use std::sync::Arc;
+
+use super::{dialect::Dialect, disasm, Instruction, Memory, Word};
+
+/// The most words any instruction occupies.
+const MAX_INSTRUCTION_LEN: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct Cached {
+    pub(super) instruction: Instruction,
+    pub(super) operands: [i64; 3],
+}
+
+/// Decoded instructions by address, shared between clones of a program until
+/// one of them changes an entry.
+#[derive(Clone, Debug)]
+pub(super) struct DecodeCache {
+    entries: Arc<Vec<Option<Cached>>>,
+    /// The version of the memory the entries agree with.
+    version: (u64, u64),
+}
+
+impl DecodeCache {
+    /// Decodes every instruction of `code` that is statically reachable from
+    /// address 0.
+    pub(super) fn new<W: Word>(code: &Memory<W>, dialect: &Dialect<W>) -> DecodeCache {
+        let image: Vec<i64> = code
+            .iter()
+            .take(code.dense_len())
+            .map_while(W::to_i64)
+            .collect();
+        let mut entries = Vec::new();
+        for address in disasm::reachable(&image, dialect).into_keys() {
+            if let Some(cached) = decode(code, dialect, address) {
+                if address >= entries.len() {
+                    entries.resize(address + 1, None);
+                }
+                entries[address] = Some(cached);
+            }
+        }
+        DecodeCache {
+            entries: Arc::new(entries),
+            version: code.version(),
+        }
+    }
+
+    /// The cache for `clone`, a fresh clone of `original`.
+    pub(super) fn for_clone<W>(&self, original: &Memory<W>, clone: &Memory<W>) -> DecodeCache {
+        let mut cache = self.clone();
+        if cache.version == original.version() {
+            cache.version = clone.version();
+        }
+        cache
+    }
+
+    /// Returns the instruction at `address`, decoding it if it is not cached
+    /// yet. Returns `None` if the instruction cannot be decoded or has an
+    /// operand that does not fit into an `i64`, leaving it to the interpreter
+    /// to deal with.
+    pub(super) fn get<W: Word>(
+        &mut self,
+        code: &Memory<W>,
+        dialect: &Dialect<W>,
+        address: usize,
+    ) -> Option<Cached> {
+        if self.version != code.version() {
+            self.sync(code, dialect);
+        }
+        if let Some(Some(cached)) = self.entries.get(address) {
+            return Some(*cached);
+        }
+
+        let cached = decode(code, dialect, address)?;
+        // Code running far past the loaded image is not worth growing the
+        // cache for.
+        if address < code.dense_len() {
+            let entries = Arc::make_mut(&mut self.entries);
+            if address >= entries.len() {
+                entries.resize(address + 1, None);
+            }
+            entries[address] = Some(cached);
+        }
+        Some(cached)
+    }
+
+    /// Drops the entries covering `address`, which the program has just
+    /// written to, so that the next lookup does not have to find out about
+    /// the write from memory.
+    pub(super) fn written<W>(&mut self, code: &Memory<W>, address: usize) {
+        let (id, writes) = self.version;
+        if code.version() == (id, writes + 1) {
+            self.invalidate(address);
+            self.version = code.version();
+        }
+    }
+
+    /// Drops the entries that `code` no longer agrees with.
+    fn sync<W: Word>(&mut self, code: &Memory<W>, dialect: &Dialect<W>) {
+        match code.written_since(self.version) {
+            Some(written) => {
+                for address in written {
+                    self.invalidate(address);
+                }
+            }
+            None => {
+                let stale: Vec<usize> = self
+                    .entries
+                    .iter()
+                    .enumerate()
+                    .filter(|(address, cached)| {
+                        cached.is_some() && decode(code, dialect, *address) != **cached
+                    })
+                    .map(|(address, _)| address)
+                    .collect();
+                if !stale.is_empty() {
+                    let entries = Arc::make_mut(&mut self.entries);
+                    for address in stale {
+                        entries[address] = None;
+                    }
+                }
+            }
+        }
+        self.version = code.version();
+    }
+
+    /// Drops the entries of every instruction that includes `address`.
+    fn invalidate(&mut self, address: usize) {
+        let first = address.saturating_sub(MAX_INSTRUCTION_LEN - 1);
+        for start in first..=address {
+            if self.covers(start, address) {
+                Arc::make_mut(&mut self.entries)[start] = None;
+            }
+        }
+    }
+
+    /// Whether the instruction cached at `start` includes `address`.
+    fn covers(&self, start: usize, address: usize) -> bool {
+        match self.entries.get(start) {
+            Some(Some(cached)) => address <= start + cached.instruction.opcode.parameter_count(),
+            _ => false,
+        }
+    }
+}
+
+/// Decodes the instruction at `address`, or returns `None` if it cannot be
+/// decoded or has an operand that does not fit into an `i64` or lies past the
+/// end of memory.
+pub(super) fn decode<W: Word>(
+    code: &Memory<W>,
+    dialect: &Dialect<W>,
+    address: usize,
+) -> Option<Cached> {
+    let instruction = dialect
+        .parse_instruction(code.get(address)?.to_i64()?)
+        .ok()?;
+    let mut operands = [0; 3];
+    for (i, operand) in operands
+        .iter_mut()
+        .enumerate()
+        .take(instruction.opcode.parameter_count())
+    {
+        *operand = code.get(address + 1 + i)?.to_i64()?;
+    }
+    Some(Cached {
+        instruction,
+        operands,
+    })
+}
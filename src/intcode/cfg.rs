@@ -0,0 +1,240 @@
+//! Control-flow graphs of programs, built from the statically reachable code
+//! found by [`disasm::reachable`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Write},
+};
+
+use super::{
+    dialect::Dialect,
+    disasm::{self, Decoded, Operand},
+    JumpCondition, Opcode, ParameterMode, Word,
+};
+
+/// How control gets from one block to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction.
+    FallThrough,
+    /// A conditional jump jumps.
+    Jump,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+/// A run of instructions that is only entered at its first instruction and
+/// only left after its last one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    pub instructions: Vec<(usize, Decoded)>,
+    pub successors: Vec<Edge>,
+    /// Whether the block ends in a jump whose target is read from memory, so
+    /// that some of its successors are unknown.
+    pub computed_jump: bool,
+    /// Reachable code addresses that instructions in the block write to in
+    /// position mode, which makes the program self-modifying.
+    pub code_writes: Vec<usize>,
+}
+
+impl Block {
+    /// One past the last word of the block.
+    pub fn end(&self) -> usize {
+        self.instructions
+            .last()
+            .map_or(self.start, |(address, decoded)| {
+                address + decoded.word_count()
+            })
+    }
+}
+
+/// The basic blocks of a program, keyed by their start address.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>,
+}
+
+impl Cfg {
+    /// Splits the code reachable from address 0 into basic blocks. Blocks start
+    /// at address 0, at immediate jump targets, and after every jump.
+    pub fn build<W: Word>(code: &[i64], dialect: &Dialect<W>) -> Cfg {
+        let instructions = disasm::reachable(code, dialect);
+        let covered: BTreeSet<usize> = instructions
+            .iter()
+            .flat_map(|(&address, decoded)| address..address + decoded.word_count())
+            .collect();
+
+        let mut leaders = BTreeSet::from([0]);
+        for (&address, decoded) in &instructions {
+            if let Opcode::Jump(_) = decoded.opcode {
+                leaders.extend(decoded.successors(address));
+            }
+        }
+        leaders.retain(|address| instructions.contains_key(address));
+
+        let mut blocks = BTreeMap::new();
+        for &start in &leaders {
+            let mut block = Block {
+                start,
+                instructions: Vec::new(),
+                successors: Vec::new(),
+                computed_jump: false,
+                code_writes: Vec::new(),
+            };
+            let mut address = start;
+            loop {
+                let decoded = &instructions[&address];
+                if let Some(target) = code_write(decoded).filter(|t| covered.contains(t)) {
+                    block.code_writes.push(target);
+                }
+                block.instructions.push((address, decoded.clone()));
+
+                let next = address + decoded.word_count();
+                match decoded.opcode {
+                    Opcode::Exit => break,
+                    Opcode::Jump(_) => {
+                        for target in decoded.successors(address) {
+                            let kind = if decoded.jump_target() == Some(target) {
+                                EdgeKind::Jump
+                            } else {
+                                EdgeKind::FallThrough
+                            };
+                            block.successors.push(Edge { target, kind });
+                        }
+                        block.successors.dedup_by_key(|edge| edge.target);
+                        block.computed_jump = is_computed_jump(decoded);
+                        break;
+                    }
+                    _ => {}
+                }
+                if !instructions.contains_key(&next) {
+                    break;
+                }
+                if leaders.contains(&next) {
+                    block.successors.push(Edge {
+                        target: next,
+                        kind: EdgeKind::FallThrough,
+                    });
+                    break;
+                }
+                address = next;
+            }
+            blocks.insert(start, block);
+        }
+
+        Cfg { blocks }
+    }
+
+    /// Renders the graph in Graphviz DOT format. Blocks ending in a computed
+    /// jump are drawn in red with a dashed edge to an unknown target, and
+    /// self-modifying blocks are filled.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        // Writing to a String cannot fail.
+        let _ = self.write_dot(&mut dot);
+        dot
+    }
+
+    fn write_dot(&self, dot: &mut String) -> fmt::Result {
+        writeln!(dot, "digraph cfg {{")?;
+        writeln!(dot, "    node [shape=box, fontname=monospace];")?;
+        let mut any_computed = false;
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (address, decoded) in &block.instructions {
+                write!(label, "{address}: {decoded}\\l")?;
+            }
+            let mut attributes = format!("label=\"{label}\"");
+            if block.computed_jump {
+                attributes.push_str(", color=red");
+            }
+            if !block.code_writes.is_empty() {
+                attributes.push_str(", style=filled, fillcolor=lightyellow");
+            }
+            writeln!(dot, "    b{} [{attributes}];", block.start)?;
+
+            for edge in &block.successors {
+                let style = match edge.kind {
+                    EdgeKind::FallThrough => "",
+                    EdgeKind::Jump => " [label=\"jump\"]",
+                };
+                writeln!(dot, "    b{} -> b{}{style};", block.start, edge.target)?;
+            }
+            if block.computed_jump {
+                any_computed = true;
+                writeln!(dot, "    b{} -> computed [style=dashed];", block.start)?;
+            }
+        }
+        if any_computed {
+            writeln!(dot, "    computed [label=\"?\", shape=diamond, color=red];")?;
+        }
+        writeln!(dot, "}}")
+    }
+}
+
+/// Lists every block with its instructions and successors.
+impl fmt::Display for Cfg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, block) in self.blocks.values().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "block {}..{}:", block.start, block.end())?;
+            for (address, decoded) in &block.instructions {
+                writeln!(f, "  {address:>6}: {decoded}")?;
+            }
+            if !block.code_writes.is_empty() {
+                let targets: Vec<String> =
+                    block.code_writes.iter().map(|a| a.to_string()).collect();
+                writeln!(f, "  writes code at {}", targets.join(", "))?;
+            }
+
+            let mut successors: Vec<String> = block
+                .successors
+                .iter()
+                .map(|edge| match edge.kind {
+                    EdgeKind::FallThrough => edge.target.to_string(),
+                    EdgeKind::Jump => format!("{} (jump)", edge.target),
+                })
+                .collect();
+            if block.computed_jump {
+                successors.push("? (computed jump)".to_owned());
+            }
+            if successors.is_empty() {
+                successors.push("end".to_owned());
+            }
+            writeln!(f, "  -> {}", successors.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `decoded` is a jump that may be taken to a target only known at run
+/// time.
+fn is_computed_jump(decoded: &Decoded) -> bool {
+    let Opcode::Jump(condition) = decoded.opcode else {
+        return false;
+    };
+    let never_taken = match decoded.operands[0] {
+        Operand {
+            mode: ParameterMode::Immediate,
+            value,
+        } => (value != 0) != (condition == JumpCondition::True),
+        _ => false,
+    };
+    decoded.jump_target().is_none() && !never_taken
+}
+
+/// The address an instruction writes to, if it is known statically.
+fn code_write(decoded: &Decoded) -> Option<usize> {
+    let operand = decoded.operands.get(decoded.opcode.target_parameter()?)?;
+    match operand.mode {
+        ParameterMode::Position => usize::try_from(operand.value).ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,81 @@
+//! The command line of `aoc19 intcode run`.
+//!
+//! `intcode run [--ascii] [--input-file <path>] <program> [inputs...]`
+//!
+//! Input comes from the arguments following the program, from the input file
+//! or, if neither is given, from stdin. In ASCII mode every argument or line
+//! of the file is sent as one line of text.
+
+use std::num::ParseIntError;
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ArgsError {
+    #[error("Missing program")]
+    MissingProgram,
+    #[error("Missing input file")]
+    MissingInputFile,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RunArgs {
+    pub ascii: bool,
+    pub input_file: Option<String>,
+    /// Path of the program to run.
+    pub program: String,
+    /// One number or, in ASCII mode, one line of text each.
+    pub inputs: Vec<String>,
+}
+
+impl RunArgs {
+    /// Parses the arguments following `run`. Options may appear anywhere.
+    pub fn parse(args: &[String]) -> Result<RunArgs, ArgsError> {
+        let mut ascii = false;
+        let mut input_file = None;
+        let mut positional = Vec::new();
+        let mut rest = args.iter();
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                "--ascii" => ascii = true,
+                "--input-file" => {
+                    input_file = Some(rest.next().ok_or(ArgsError::MissingInputFile)?.clone());
+                }
+                _ => positional.push(arg.clone()),
+            }
+        }
+        let (program, inputs) = positional.split_first().ok_or(ArgsError::MissingProgram)?;
+        Ok(RunArgs {
+            ascii,
+            input_file,
+            program: program.clone(),
+            inputs: inputs.to_vec(),
+        })
+    }
+
+    /// Whether input has to come from stdin because none was given.
+    pub fn is_interactive(&self) -> bool {
+        self.inputs.is_empty() && self.input_file.is_none()
+    }
+
+    /// Appends the inputs in `contents`, the contents of the input file: its
+    /// lines in ASCII mode, otherwise the numbers separated by commas or
+    /// whitespace.
+    pub fn add_inputs(&mut self, contents: &str) {
+        if self.ascii {
+            self.inputs.extend(contents.lines().map(str::to_owned));
+        } else {
+            self.inputs.extend(
+                contents
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|token| !token.is_empty())
+                    .map(str::to_owned),
+            );
+        }
+    }
+
+    /// The inputs as numbers.
+    pub fn values(&self) -> Result<Vec<i64>, ParseIntError> {
+        self.inputs.iter().map(|s| s.parse()).collect()
+    }
+}
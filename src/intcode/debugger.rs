@@ -0,0 +1,442 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+use super::{disasm, Fault, Program, ProgramState};
+
+/// The kind of memory access a watchpoint reacts to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    Access,
+}
+
+impl Watch {
+    fn matches(self, read: bool, written: bool) -> bool {
+        match self {
+            Watch::Read => read,
+            Watch::Write => written,
+            Watch::Access => read || written,
+        }
+    }
+}
+
+/// Why the debugger handed control back to the user.
+#[derive(Debug)]
+pub enum Stop {
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint { address: usize, written: bool },
+    Input(i64),
+    Output(i64),
+    ExpectingInput,
+    Exited,
+    Fault(Fault),
+    Terminated,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Stepped => write!(f, "Stepped"),
+            Stop::Breakpoint(address) => write!(f, "Hit breakpoint at {address}"),
+            Stop::Watchpoint {
+                address,
+                written: true,
+            } => write!(f, "Watchpoint: wrote to {address}"),
+            Stop::Watchpoint {
+                address,
+                written: false,
+            } => write!(f, "Watchpoint: read from {address}"),
+            Stop::Input(value) => write!(f, "Program read input {value}"),
+            Stop::Output(value) => write!(f, "Program wrote output {value}"),
+            Stop::ExpectingInput => write!(f, "Program is waiting for input"),
+            Stop::Exited => write!(f, "Program exited"),
+            Stop::Fault(err) => write!(f, "Program faulted: {err}"),
+            Stop::Terminated => write!(f, "Program is not running"),
+        }
+    }
+}
+
+/// Drives a [`Program`] one instruction at a time, stopping on breakpoints,
+/// watchpoints and I/O events.
+pub struct Debugger {
+    program: Program,
+    input: VecDeque<i64>,
+    output: Vec<i64>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeMap<usize, Watch>,
+    break_on_input: bool,
+    break_on_output: bool,
+    terminated: bool,
+}
+
+impl Debugger {
+    pub fn new(program: Program) -> Debugger {
+        Debugger {
+            program,
+            input: VecDeque::new(),
+            output: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            break_on_input: false,
+            break_on_output: false,
+            terminated: false,
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn push_input(&mut self, value: i64) {
+        self.input.push_back(value);
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn add_watchpoint(&mut self, address: usize, watch: Watch) {
+        self.watchpoints.insert(address, watch);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.remove(&address).is_some()
+    }
+
+    pub fn set_break_on_input(&mut self, enabled: bool) {
+        self.break_on_input = enabled;
+    }
+
+    pub fn set_break_on_output(&mut self, enabled: bool) {
+        self.break_on_output = enabled;
+    }
+
+    /// Returns and forgets the output produced since the last call.
+    pub fn take_output(&mut self) -> Vec<i64> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Executes up to `count` instructions.
+    pub fn step(&mut self, count: u64) -> Stop {
+        self.resume(Some(count), None)
+    }
+
+    /// Runs until the instruction directly after the current one is reached,
+    /// stepping over any loop or jump that eventually comes back.
+    pub fn step_over(&mut self) -> Stop {
+        let pc = self.program.program_counter();
+        let after = self
+            .instruction_length(pc)
+            .and_then(|len| pc.checked_add(len));
+        self.resume(None, after)
+    }
+
+    pub fn resume_until_stop(&mut self) -> Stop {
+        self.resume(None, None)
+    }
+
+    fn resume(&mut self, mut remaining: Option<u64>, until: Option<usize>) -> Stop {
+        if self.terminated {
+            return Stop::Terminated;
+        }
+
+        let mut first = true;
+        loop {
+            let pc = self.program.program_counter();
+            if !first {
+                if self.breakpoints.contains(&pc) {
+                    return Stop::Breakpoint(pc);
+                }
+                if until == Some(pc) {
+                    return Stop::Stepped;
+                }
+            }
+            first = false;
+
+            if remaining == Some(0) {
+                return Stop::Stepped;
+            }
+            remaining = remaining.map(|r| r - 1);
+
+            let step = match self.program.step(&mut self.input, &mut self.output) {
+                Ok(step) => step,
+                Err(err) => {
+                    self.terminated = true;
+                    return Stop::Fault(err);
+                }
+            };
+
+            match step.state {
+                Some(ProgramState::Exited) => {
+                    self.terminated = true;
+                    return Stop::Exited;
+                }
+                Some(ProgramState::ExpectingInput) => return Stop::ExpectingInput,
+                Some(ProgramState::BudgetExhausted | ProgramState::ProducedOutput) => {
+                    return Stop::Stepped
+                }
+                None => {}
+            }
+
+            for (&address, &watch) in &self.watchpoints {
+                let read = step
+                    .parameters
+                    .iter()
+                    .any(|p| p.value.is_some() && p.address == Some(address));
+                let written = step.write.is_some_and(|w| w.address == address);
+                if watch.matches(read, written) {
+                    return Stop::Watchpoint { address, written };
+                }
+            }
+
+            if let (true, Some(value)) = (self.break_on_input, step.input) {
+                return Stop::Input(value);
+            }
+
+            if let (true, Some(value)) = (self.break_on_output, step.output) {
+                return Stop::Output(value);
+            }
+        }
+    }
+
+    fn word(&self, address: usize) -> i64 {
+        self.program.memory().get(address).copied().unwrap_or(0)
+    }
+
+    fn instruction_length(&self, pc: usize) -> Option<usize> {
+        // No instruction is longer than four words.
+        let window: Vec<i64> = (pc..pc.saturating_add(4)).map(|a| self.word(a)).collect();
+        let decoded = disasm::decode(&window, self.program.dialect(), 0).ok()?;
+        Some(decoded.word_count())
+    }
+
+    fn describe_current(&self, out: &mut impl Write) -> io::Result<()> {
+        let pc = self.program.program_counter();
+        let len = self.instruction_length(pc).unwrap_or(1);
+        let words: Vec<String> = (pc..pc.saturating_add(len))
+            .map(|a| self.word(a).to_string())
+            .collect();
+        writeln!(out, "=> {pc}: {}", words.join(","))
+    }
+
+    /// Shows `count` words from `start` on, but no more than
+    /// [`MAX_DUMP_WORDS`] and none past the end of the address space.
+    fn dump_memory(&self, out: &mut impl Write, start: usize, count: usize) -> io::Result<()> {
+        let stop = start.saturating_add(count.min(MAX_DUMP_WORDS));
+        for row in (start..stop).step_by(8) {
+            let end = row.saturating_add(8).min(stop);
+            let words: Vec<String> = (row..end).map(|a| self.word(a).to_string()).collect();
+            writeln!(out, "{row:>6}: {}", words.join(" "))?;
+        }
+        if count > MAX_DUMP_WORDS {
+            writeln!(out, "(only the first {MAX_DUMP_WORDS} words are shown)")?;
+        }
+        Ok(())
+    }
+
+    fn dump_registers(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "pc            {}", self.program.program_counter())?;
+        writeln!(out, "relative base {}", self.program.relative_base())?;
+        writeln!(out, "executed      {}", self.program.executed())?;
+        writeln!(out, "memory size   {}", self.program.memory().len())?;
+        writeln!(out, "input queue   {:?}", self.input)
+    }
+
+    fn dump_breakpoints(&self, out: &mut impl Write) -> io::Result<()> {
+        for address in &self.breakpoints {
+            writeln!(out, "breakpoint at {address}")?;
+        }
+        for (address, watch) in &self.watchpoints {
+            writeln!(out, "watchpoint ({watch:?}) at {address}")?;
+        }
+        if self.break_on_input {
+            writeln!(out, "break on input")?;
+        }
+        if self.break_on_output {
+            writeln!(out, "break on output")?;
+        }
+        Ok(())
+    }
+
+    /// Executes a single REPL command. Returns `Ok(false)` once the user asked
+    /// to quit.
+    pub fn command(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(true);
+        };
+        let args: Vec<&str> = words.collect();
+
+        let stop = match (command, args.as_slice()) {
+            ("s" | "step", []) => Some(self.step(1)),
+            ("s" | "step", [count]) => match count.parse() {
+                Ok(count) => Some(self.step(count)),
+                Err(_) => return usage(out, "step [count]"),
+            },
+            ("n" | "next", []) => Some(self.step_over()),
+            ("c" | "continue", []) => Some(self.resume_until_stop()),
+
+            ("b" | "break", ["input"]) => {
+                self.set_break_on_input(true);
+                None
+            }
+            ("b" | "break", ["output"]) => {
+                self.set_break_on_output(true);
+                None
+            }
+            ("b" | "break", [address]) => match address.parse() {
+                Ok(address) => {
+                    self.add_breakpoint(address);
+                    None
+                }
+                Err(_) => return usage(out, "break <address>|input|output"),
+            },
+            ("d" | "delete", ["input"]) => {
+                self.set_break_on_input(false);
+                None
+            }
+            ("d" | "delete", ["output"]) => {
+                self.set_break_on_output(false);
+                None
+            }
+            ("d" | "delete", [address]) => match address.parse() {
+                Ok(address) => {
+                    if !self.remove_breakpoint(address) {
+                        writeln!(out, "No breakpoint at {address}")?;
+                    }
+                    None
+                }
+                Err(_) => return usage(out, "delete <address>|input|output"),
+            },
+
+            ("w" | "watch", [address, kind @ ..]) if kind.len() <= 1 => {
+                let watch = match kind.first().copied().unwrap_or("rw") {
+                    "r" => Watch::Read,
+                    "w" => Watch::Write,
+                    "rw" => Watch::Access,
+                    _ => return usage(out, "watch <address> [r|w|rw]"),
+                };
+                match address.parse() {
+                    Ok(address) => {
+                        self.add_watchpoint(address, watch);
+                        None
+                    }
+                    Err(_) => return usage(out, "watch <address> [r|w|rw]"),
+                }
+            }
+            ("unwatch", [address]) => match address.parse() {
+                Ok(address) => {
+                    if !self.remove_watchpoint(address) {
+                        writeln!(out, "No watchpoint at {address}")?;
+                    }
+                    None
+                }
+                Err(_) => return usage(out, "unwatch <address>"),
+            },
+            ("i" | "info", []) => {
+                self.dump_breakpoints(out)?;
+                None
+            }
+
+            ("input", values) if !values.is_empty() => {
+                match values
+                    .iter()
+                    .map(|v| v.parse())
+                    .collect::<Result<Vec<i64>, _>>()
+                {
+                    Ok(values) => self.input.extend(values),
+                    Err(_) => return usage(out, "input <value>..."),
+                }
+                None
+            }
+            ("r" | "regs", []) => {
+                self.dump_registers(out)?;
+                None
+            }
+            ("x" | "mem", [start, count @ ..]) if count.len() <= 1 => {
+                let count = count.first().map_or(Ok(8), |c| c.parse());
+                match (start.parse(), count) {
+                    (Ok(start), Ok(count)) => self.dump_memory(out, start, count)?,
+                    _ => return usage(out, "mem <address> [count]"),
+                }
+                None
+            }
+
+            ("h" | "help", []) => {
+                writeln!(out, "{HELP}")?;
+                None
+            }
+            ("q" | "quit", []) => return Ok(false),
+            _ => {
+                writeln!(out, "Unknown command, try `help`")?;
+                None
+            }
+        };
+
+        for value in self.take_output() {
+            writeln!(out, "output: {value}")?;
+        }
+        if let Some(stop) = stop {
+            writeln!(out, "{stop}")?;
+            if !self.terminated {
+                self.describe_current(out)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Reads commands from `input` until it ends or the user quits. An empty
+    /// line repeats the previous command.
+    pub fn repl(&mut self, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+        self.describe_current(&mut out)?;
+        let mut previous = String::new();
+        let mut lines = input.lines();
+        loop {
+            write!(out, "(debug) ")?;
+            out.flush()?;
+            let Some(line) = lines.next() else {
+                return Ok(());
+            };
+            let line = line?;
+            if !line.trim().is_empty() {
+                previous = line;
+            }
+            if !self.command(&previous, &mut out)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn usage(out: &mut impl Write, usage: &str) -> io::Result<bool> {
+    writeln!(out, "Usage: {usage}")?;
+    Ok(true)
+}
+
+/// The most words a single `mem` command shows.
+const MAX_DUMP_WORDS: usize = 4096;
+
+const HELP: &str = "\
+step [count]               execute one or more instructions (s)
+next                       run until the following instruction is reached (n)
+continue                   run until something interesting happens (c)
+break <address>            stop before executing the instruction at address (b)
+break input|output         stop after the program reads input or writes output
+delete <address>           remove a breakpoint (d)
+delete input|output        stop breaking on input or output
+watch <address> [r|w|rw]   stop after the address is read and/or written (w)
+unwatch <address>          remove a watchpoint
+info                       list breakpoints and watchpoints (i)
+input <value>...           queue values for the program to read
+regs                       show the program counter and other registers (r)
+mem <address> [count]      dump memory (x)
+quit                       leave the debugger (q)";
@@ -0,0 +1,261 @@
+//! Memory-mapped devices.
+//!
+//! A device attached with [`Program::attach`](super::Program::attach) takes
+//! over a range of addresses: parameters read from there and results written
+//! there go to the device instead of memory. Instructions are always fetched
+//! from memory, and the program's `code` only ever shows memory.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use thiserror::Error;
+
+use super::Word;
+
+/// Something living at a range of addresses.
+pub trait Device<W = i64>: Send {
+    /// Returns the word at `offset` from the start of the device's range.
+    /// `clock` is the number of instructions the program executed before the
+    /// one reading.
+    fn read(&mut self, offset: usize, clock: u64) -> W;
+
+    /// Stores `value` at `offset` from the start of the device's range.
+    fn write(&mut self, offset: usize, value: W, clock: u64);
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DeviceError {
+    #[error("Device range {0:?} is empty")]
+    EmptyRange(Range<usize>),
+    #[error("Device range {range:?} overlaps the device at {existing:?}")]
+    Overlap {
+        range: Range<usize>,
+        existing: Range<usize>,
+    },
+}
+
+struct Mapping<W> {
+    range: Range<usize>,
+    device: Arc<Mutex<dyn Device<W>>>,
+}
+
+/// The devices attached to a program. Clones share the devices themselves.
+pub(super) struct Devices<W> {
+    mappings: Vec<Mapping<W>>,
+}
+
+impl<W> Devices<W> {
+    pub(super) fn new() -> Devices<W> {
+        Devices {
+            mappings: Vec::new(),
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    pub(super) fn attach(
+        &mut self,
+        range: Range<usize>,
+        device: Arc<Mutex<dyn Device<W>>>,
+    ) -> Result<(), DeviceError> {
+        if range.is_empty() {
+            return Err(DeviceError::EmptyRange(range));
+        }
+        if let Some(mapping) = self
+            .mappings
+            .iter()
+            .find(|mapping| range.start < mapping.range.end && mapping.range.start < range.end)
+        {
+            return Err(DeviceError::Overlap {
+                range,
+                existing: mapping.range.clone(),
+            });
+        }
+        self.mappings.push(Mapping { range, device });
+        Ok(())
+    }
+
+    /// Reads `address` from the device mapped there, if any.
+    pub(super) fn read(&self, address: usize, clock: u64) -> Option<W> {
+        let mapping = self.find(address)?;
+        let mut device = mapping.device.lock().unwrap();
+        Some(device.read(address - mapping.range.start, clock))
+    }
+
+    /// Writes `value` to the device mapped at `address`, or hands it back if
+    /// there is none.
+    pub(super) fn write(&self, address: usize, value: W, clock: u64) -> Option<W> {
+        match self.find(address) {
+            Some(mapping) => {
+                let mut device = mapping.device.lock().unwrap();
+                device.write(address - mapping.range.start, value, clock);
+                None
+            }
+            None => Some(value),
+        }
+    }
+
+    fn find(&self, address: usize) -> Option<&Mapping<W>> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.range.contains(&address))
+    }
+}
+
+impl<W> Clone for Devices<W> {
+    fn clone(&self) -> Devices<W> {
+        Devices {
+            mappings: self
+                .mappings
+                .iter()
+                .map(|mapping| Mapping {
+                    range: mapping.range.clone(),
+                    device: Arc::clone(&mapping.device),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Counts executed instructions. Reading returns the number executed since
+/// the counter was last written to, whatever the value written. Clones of a
+/// program share the counter, so a program that has executed fewer
+/// instructions than the one that last wrote to it reads 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CycleCounter {
+    start: u64,
+}
+
+impl CycleCounter {
+    pub fn new() -> CycleCounter {
+        CycleCounter::default()
+    }
+}
+
+impl<W: Word> Device<W> for CycleCounter {
+    fn read(&mut self, _: usize, clock: u64) -> W {
+        W::from(clock.saturating_sub(self.start) as i64)
+    }
+
+    fn write(&mut self, _: usize, _: W, clock: u64) {
+        self.start = clock;
+    }
+}
+
+/// A deterministic source of random numbers. Every read returns the next
+/// number, which is below the bound last written, or any non-negative number
+/// if that was not positive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Random {
+    state: u64,
+    bound: Option<u64>,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random {
+            // The generator never leaves the all-zero state.
+            state: seed.max(1),
+            bound: None,
+        }
+    }
+
+    /// Advances the xorshift64* generator.
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+impl<W: Word> Device<W> for Random {
+    fn read(&mut self, _: usize, _: u64) -> W {
+        let number = self.next() >> 1;
+        W::from(self.bound.map_or(number, |bound| number % bound) as i64)
+    }
+
+    fn write(&mut self, _: usize, value: W, _: u64) {
+        self.bound = value
+            .to_i64()
+            .and_then(|bound| u64::try_from(bound).ok())
+            .filter(|&bound| bound > 0);
+    }
+}
+
+/// A grid of pixels, one word each and row by row, followed by a word that
+/// renders the grid to the sink when written to. Pixels that are 0 are drawn
+/// blank and all others as `#`.
+pub struct Framebuffer<O> {
+    width: usize,
+    height: usize,
+    pixels: Vec<i64>,
+    sink: O,
+}
+
+impl<O: Write + Send> Framebuffer<O> {
+    pub fn new(width: usize, height: usize, sink: O) -> Framebuffer<O> {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; width * height],
+            sink,
+        }
+    }
+
+    /// The number of addresses the framebuffer occupies.
+    pub fn size(&self) -> usize {
+        self.pixels.len() + 1
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> i64 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn sink(&self) -> &O {
+        &self.sink
+    }
+
+    fn render(&mut self) -> io::Result<()> {
+        let frame = self.to_string();
+        self.sink.write_all(frame.as_bytes())?;
+        self.sink.flush()
+    }
+}
+
+impl<O> fmt::Display for Framebuffer<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.pixels.chunks(self.width.max(1)).take(self.height) {
+            let line: String = row
+                .iter()
+                .map(|&pixel| if pixel == 0 { ' ' } else { '#' })
+                .collect();
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Pixels hold machine-sized words; larger ones are drawn like any other
+/// nonzero pixel.
+impl<W: Word, O: Write + Send> Device<W> for Framebuffer<O> {
+    fn read(&mut self, offset: usize, _: u64) -> W {
+        W::from(self.pixels.get(offset).copied().unwrap_or(0))
+    }
+
+    fn write(&mut self, offset: usize, value: W, _: u64) {
+        match self.pixels.get_mut(offset) {
+            Some(pixel) => *pixel = value.to_i64().unwrap_or(1),
+            // A sink that fails cannot be reported to the program.
+            None => {
+                let _ = self.render();
+            }
+        }
+    }
+}
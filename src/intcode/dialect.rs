@@ -0,0 +1,657 @@
+//! The instruction set a [`Program`](super::Program) understands.
+//!
+//! A [`Dialect`] maps every opcode to an [`Operation`], which says how many
+//! parameters the instruction has, which of them it writes to, and what it
+//! does when executed. The built-in instructions are operations like any
+//! other, so a dialect can drop them, replace them or add new ones, and every
+//! [`Program`](super::Program) can be given a dialect of its own.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use thiserror::Error;
+
+use super::{
+    device::Devices, narrow, ArithmeticOperation, Comparison, ExecutionError, Input, InputMode,
+    Instruction, JumpCondition, Memory, MemoryWrite, Opcode, Operands, Output, OverflowPolicy,
+    OverflowingOperation, Parameter, ParameterMode, Program, ProgramState, Word,
+};
+
+/// The number of distinct opcodes, which are the last two digits of an
+/// instruction word.
+const OPCODES: usize = 100;
+
+/// Something an instruction does.
+pub trait Operation<W: Word = i64>: Send + Sync {
+    /// The name used in disassembly listings, traces and profiles.
+    fn mnemonic(&self) -> &'static str;
+
+    /// The number of parameters following the instruction word.
+    fn parameter_count(&self) -> usize;
+
+    /// Whether the parameter with the given index is an address the
+    /// instruction writes to, which makes immediate mode invalid for it.
+    fn is_target(&self, parameter: usize) -> bool;
+
+    /// Executes the instruction. Parameters have to be fetched in order with
+    /// [`Machine::read`] and [`Machine::target`]; those not fetched are
+    /// skipped.
+    fn execute(&self, machine: &mut Machine<'_, W>) -> Result<(), ExecutionError>;
+}
+
+/// An [`Operation`] whose behaviour is a closure.
+pub struct CustomOperation<F> {
+    mnemonic: &'static str,
+    parameters: usize,
+    targets: Vec<usize>,
+    behaviour: F,
+}
+
+impl<F> CustomOperation<F> {
+    /// Describes an instruction with `parameters` parameters, of which those
+    /// listed in `targets` are written to.
+    pub fn new<W>(
+        mnemonic: &'static str,
+        parameters: usize,
+        targets: &[usize],
+        behaviour: F,
+    ) -> CustomOperation<F>
+    where
+        W: Word,
+        F: Fn(&mut Machine<'_, W>) -> Result<(), ExecutionError>,
+    {
+        CustomOperation {
+            mnemonic,
+            parameters,
+            targets: targets.to_vec(),
+            behaviour,
+        }
+    }
+}
+
+impl<W, F> Operation<W> for CustomOperation<F>
+where
+    W: Word,
+    F: Fn(&mut Machine<'_, W>) -> Result<(), ExecutionError> + Send + Sync,
+{
+    fn mnemonic(&self) -> &'static str {
+        self.mnemonic
+    }
+
+    fn parameter_count(&self) -> usize {
+        self.parameters
+    }
+
+    fn is_target(&self, parameter: usize) -> bool {
+        self.targets.contains(&parameter)
+    }
+
+    fn execute(&self, machine: &mut Machine<'_, W>) -> Result<(), ExecutionError> {
+        (self.behaviour)(machine)
+    }
+}
+
+/// The built-in instructions.
+impl<W: Word> Operation<W> for Opcode {
+    fn mnemonic(&self) -> &'static str {
+        Opcode::mnemonic(*self)
+    }
+
+    fn parameter_count(&self) -> usize {
+        Opcode::parameter_count(*self)
+    }
+
+    fn is_target(&self, parameter: usize) -> bool {
+        Opcode::is_target(*self, parameter)
+    }
+
+    fn execute(&self, machine: &mut Machine<'_, W>) -> Result<(), ExecutionError> {
+        execute_builtin(*self, machine)
+    }
+}
+
+/// Everything the built-in instructions need from the program executing them.
+/// Besides [`Machine`], the interpreter's lean loop implements it, so that
+/// both run the built-ins through [`execute_builtin`].
+pub(super) trait BuiltinMachine<W: Word> {
+    fn pc(&self) -> usize;
+    fn overflow_policy(&self) -> OverflowPolicy;
+    fn read(&mut self) -> Result<W, ExecutionError>;
+    fn target(&mut self) -> Result<usize, ExecutionError>;
+    fn write(&mut self, address: usize, value: W) -> Result<(), ExecutionError>;
+    fn input(&mut self) -> Result<Option<W>, ExecutionError>;
+    fn output(&mut self, value: W);
+    fn jump(&mut self, target: W) -> Result<(), ExecutionError>;
+    fn adjust_relative_base(&mut self, offset: W) -> Result<(), ExecutionError>;
+    fn exit(&mut self);
+}
+
+/// Executes a built-in instruction. Always inlined, so that the lean loop gets
+/// a copy specialised to its own machine instead of a call per instruction.
+#[inline(always)]
+pub(super) fn execute_builtin<W, M>(opcode: Opcode, machine: &mut M) -> Result<(), ExecutionError>
+where
+    W: Word,
+    M: BuiltinMachine<W> + ?Sized,
+{
+    match opcode {
+        Opcode::Arithmetic(op) => {
+            let lhs = machine.read()?;
+            let rhs = machine.read()?;
+            let target = machine.target()?;
+            let policy = machine.overflow_policy();
+            let result = match op {
+                ArithmeticOperation::Add => lhs.add(&rhs, policy),
+                ArithmeticOperation::Mul => lhs.mul(&rhs, policy),
+            }
+            .ok_or(ExecutionError::Overflow {
+                pc: machine.pc(),
+                op: op.into(),
+            })?;
+            machine.write(target, result)
+        }
+
+        Opcode::Store => {
+            let target = machine.target()?;
+            match machine.input()? {
+                Some(value) => machine.write(target, value),
+                None => Ok(()),
+            }
+        }
+
+        Opcode::Jump(condition) => {
+            let value = machine.read()?;
+            let target = machine.read()?;
+            let satisfied = match condition {
+                JumpCondition::True => value != W::from(0),
+                JumpCondition::False => value == W::from(0),
+            };
+            if satisfied {
+                machine.jump(target)?;
+            }
+            Ok(())
+        }
+
+        Opcode::Compare(comparison) => {
+            let lhs = machine.read()?;
+            let rhs = machine.read()?;
+            let target = machine.target()?;
+            let fulfilled = match comparison {
+                Comparison::LessThan => lhs < rhs,
+                Comparison::Equals => lhs == rhs,
+            };
+            machine.write(target, W::from(fulfilled.into()))
+        }
+
+        Opcode::Print => {
+            let value = machine.read()?;
+            machine.output(value);
+            Ok(())
+        }
+
+        Opcode::AdjustRelativeBase => {
+            let offset = machine.read()?;
+            machine.adjust_relative_base(offset)
+        }
+
+        Opcode::Exit => {
+            machine.exit();
+            Ok(())
+        }
+
+        // Registered operations are executed through the dialect, never
+        // through their description.
+        Opcode::Custom(custom) => Err(ExecutionError::UnknownOpcode(custom.code.into())),
+    }
+}
+
+/// An opcode registered with [`Dialect::register`], described well enough for
+/// disassembly, traces and profiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CustomOpcode {
+    pub code: u8,
+    parameters: u8,
+    /// One bit per parameter that is written to.
+    targets: u8,
+    /// Index into [`MNEMONICS`].
+    mnemonic: u32,
+}
+
+impl CustomOpcode {
+    pub fn mnemonic(self) -> &'static str {
+        MNEMONICS.lock().unwrap()[self.mnemonic as usize]
+    }
+
+    pub fn parameter_count(self) -> usize {
+        self.parameters.into()
+    }
+
+    pub fn is_target(self, parameter: usize) -> bool {
+        parameter < self.parameter_count() && self.targets & (1 << parameter) != 0
+    }
+}
+
+/// Every mnemonic ever registered, so that a [`CustomOpcode`] can refer to
+/// its mnemonic by index and opcodes stay a few bytes small.
+static MNEMONICS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+fn intern(mnemonic: &'static str) -> u32 {
+    let mut mnemonics = MNEMONICS.lock().unwrap();
+    let index = match mnemonics.iter().position(|&known| known == mnemonic) {
+        Some(index) => index,
+        None => {
+            mnemonics.push(mnemonic);
+            mnemonics.len() - 1
+        }
+    };
+    index as u32
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DialectError {
+    #[error("Opcode {0} is not between 0 and 99")]
+    InvalidCode(i64),
+    #[error("`{mnemonic}` has {parameters} parameters, but at most 3 are possible")]
+    TooManyParameters {
+        mnemonic: &'static str,
+        parameters: usize,
+    },
+}
+
+struct Entry<W: Word> {
+    opcode: Opcode,
+    operation: Arc<dyn Operation<W>>,
+}
+
+impl<W: Word> Clone for Entry<W> {
+    fn clone(&self) -> Entry<W> {
+        Entry {
+            opcode: self.opcode,
+            operation: Arc::clone(&self.operation),
+        }
+    }
+}
+
+/// Opcodes and what they do. Cloning is cheap, so every program can carry a
+/// dialect of its own.
+pub struct Dialect<W: Word = i64> {
+    entries: Arc<Vec<Option<Entry<W>>>>,
+}
+
+impl<W: Word> Dialect<W> {
+    /// A dialect without any instructions.
+    pub fn empty() -> Dialect<W> {
+        Dialect {
+            entries: Arc::new(vec![None; OPCODES]),
+        }
+    }
+
+    /// The instruction set of the 2019 puzzles.
+    pub fn standard() -> Dialect<W> {
+        let mut entries = vec![None; OPCODES];
+        for (code, entry) in entries.iter_mut().enumerate() {
+            if let Ok(opcode) = Program::parse_opcode(code as i64) {
+                *entry = Some(Entry {
+                    opcode,
+                    operation: Arc::new(opcode),
+                });
+            }
+        }
+        Dialect {
+            entries: Arc::new(entries),
+        }
+    }
+
+    /// Makes `code` execute `operation`, replacing whatever it did before.
+    pub fn register<O>(&mut self, code: i64, operation: O) -> Result<(), DialectError>
+    where
+        O: Operation<W> + 'static,
+    {
+        let index = Dialect::<W>::index(code).ok_or(DialectError::InvalidCode(code))?;
+        let parameters = operation.parameter_count();
+        if parameters > 3 {
+            return Err(DialectError::TooManyParameters {
+                mnemonic: operation.mnemonic(),
+                parameters,
+            });
+        }
+        let targets = (0..parameters)
+            .filter(|&parameter| operation.is_target(parameter))
+            .fold(0, |targets, parameter| targets | 1 << parameter);
+        let opcode = Opcode::Custom(CustomOpcode {
+            code: index as u8,
+            parameters: parameters as u8,
+            targets,
+            mnemonic: intern(operation.mnemonic()),
+        });
+        Arc::make_mut(&mut self.entries)[index] = Some(Entry {
+            opcode,
+            operation: Arc::new(operation),
+        });
+        Ok(())
+    }
+
+    /// Makes `code` an unknown opcode.
+    pub fn remove(&mut self, code: i64) {
+        if let Some(index) = Dialect::<W>::index(code) {
+            Arc::make_mut(&mut self.entries)[index] = None;
+        }
+    }
+
+    /// The opcode `code` stands for.
+    pub fn opcode(&self, code: i64) -> Result<Opcode, ExecutionError> {
+        self.entry(code)
+            .map(|entry| entry.opcode)
+            .ok_or(ExecutionError::UnknownOpcode(code))
+    }
+
+    pub(super) fn operation(&self, code: i64) -> Result<&dyn Operation<W>, ExecutionError> {
+        self.entry(code)
+            .map(|entry| &*entry.operation)
+            .ok_or(ExecutionError::UnknownOpcode(code))
+    }
+
+    pub(super) fn parse_instruction(&self, value: i64) -> Result<Instruction, ExecutionError> {
+        Ok(Instruction::new(self.opcode(value % 100)?, value))
+    }
+
+    fn entry(&self, code: i64) -> Option<&Entry<W>> {
+        self.entries.get(Dialect::<W>::index(code)?)?.as_ref()
+    }
+
+    fn index(code: i64) -> Option<usize> {
+        usize::try_from(code).ok().filter(|&index| index < OPCODES)
+    }
+}
+
+impl<W: Word> Clone for Dialect<W> {
+    fn clone(&self) -> Dialect<W> {
+        Dialect {
+            entries: Arc::clone(&self.entries),
+        }
+    }
+}
+
+impl<W: Word> Default for Dialect<W> {
+    fn default() -> Dialect<W> {
+        Dialect::standard()
+    }
+}
+
+/// Lists the registered opcodes with their mnemonics.
+impl<W: Word> fmt::Debug for Dialect<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.entries.iter().enumerate().filter_map(|(code, entry)| {
+                entry.as_ref().map(|entry| (code, entry.opcode.mnemonic()))
+            }))
+            .finish()
+    }
+}
+
+/// What an instruction did besides fetching its parameters.
+pub(super) struct Effects<W> {
+    pub(super) write: Option<MemoryWrite<W>>,
+    pub(super) input: Option<W>,
+    pub(super) output: Option<W>,
+    pub(super) jump: Option<usize>,
+    pub(super) state: Option<ProgramState>,
+}
+
+impl<W> Default for Effects<W> {
+    fn default() -> Effects<W> {
+        Effects {
+            write: None,
+            input: None,
+            output: None,
+            jump: None,
+            state: None,
+        }
+    }
+}
+
+/// The state of a program while one of its instructions executes.
+pub struct Machine<'a, W: Word = i64> {
+    pub(super) code: &'a mut Memory<W>,
+    pub(super) relative_base: &'a mut i64,
+    pub(super) overflow_policy: OverflowPolicy,
+    pub(super) input_mode: InputMode,
+    pub(super) input: &'a mut dyn Input<W>,
+    pub(super) output: &'a mut dyn Output<W>,
+    pub(super) devices: &'a Devices<W>,
+    /// The number of instructions executed before this one.
+    pub(super) clock: u64,
+    pub(super) operands: &'a mut Operands<W>,
+    pub(super) effects: Effects<W>,
+}
+
+impl<W: Word> Machine<'_, W> {
+    /// Address of the executing instruction.
+    pub fn pc(&self) -> usize {
+        self.operands.pc
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        *self.relative_base
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    /// Memory, without any devices mapped into it.
+    pub fn memory(&self) -> &Memory<W> {
+        self.code
+    }
+
+    /// Direct access to memory, bypassing devices. Writes made this way are
+    /// not reported in the [`super::Step`].
+    pub fn memory_mut(&mut self) -> &mut Memory<W> {
+        self.code
+    }
+
+    /// Reads the word at `address` like a parameter would, from the device
+    /// mapped there if there is one.
+    pub fn load(&self, address: usize) -> W {
+        self.devices
+            .read(address, self.clock)
+            .unwrap_or_else(|| self.get(address))
+    }
+
+    /// Fetches the next parameter and returns its value.
+    pub fn read(&mut self) -> Result<W, ExecutionError> {
+        let mode = self.operands.next_mode()?;
+        let raw = self.raw_parameter()?;
+        let address = match mode {
+            ParameterMode::Immediate => None,
+            _ => Some(self.effective_address(mode, &raw)?),
+        };
+        let value = match address {
+            Some(address) => self.load(address),
+            None => raw.clone(),
+        };
+        if self.operands.detailed {
+            self.operands.parameters.push(Parameter {
+                mode,
+                raw,
+                address,
+                value: Some(value.clone()),
+            });
+        }
+        Ok(value)
+    }
+
+    /// Fetches the next parameter and returns the address it refers to.
+    pub fn target(&mut self) -> Result<usize, ExecutionError> {
+        let mode = self.operands.next_mode()?;
+        let raw = self.raw_parameter()?;
+        if mode == ParameterMode::Immediate {
+            return Err(ExecutionError::InvalidImmediateParameter);
+        }
+        let address = self.effective_address(mode, &raw)?;
+        if self.operands.detailed {
+            self.operands.parameters.push(Parameter {
+                mode,
+                raw,
+                address: Some(address),
+                value: None,
+            });
+        }
+        Ok(address)
+    }
+
+    /// Writes `value` to `address`, or to the device mapped there. Only the
+    /// last write of an instruction is reported in its [`super::Step`].
+    pub fn write(&mut self, address: usize, value: W) -> Result<(), ExecutionError> {
+        if let Some(value) = self.devices.write(address, value.clone(), self.clock) {
+            *self.code.word_mut(address) = value;
+        }
+        self.effects.write = Some(MemoryWrite { address, value });
+        Ok(())
+    }
+
+    /// Reads a value from the program's input. `None` means that there is no
+    /// input yet: the program pauses and retries the instruction later, so
+    /// the instruction should not have done anything else before.
+    pub fn input(&mut self) -> Result<Option<W>, ExecutionError> {
+        match self.input.read() {
+            Some(value) => {
+                self.effects.input = Some(value.clone());
+                Ok(Some(value))
+            }
+            None if self.input_mode == InputMode::Strict => {
+                Err(ExecutionError::UnexpectedEndOfInput { pc: self.pc() })
+            }
+            None => {
+                self.effects.state = Some(ProgramState::ExpectingInput);
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn output(&mut self, value: W) {
+        self.output.write(value.clone());
+        self.effects.output = Some(value);
+    }
+
+    /// Continues execution at `target` instead of the next instruction.
+    pub fn jump(&mut self, target: W) -> Result<(), ExecutionError> {
+        let target = narrow(&target)?;
+        self.operands.address = Some(target);
+        self.effects.jump = Some(usize::try_from(target).map_err(|_| ExecutionError::OutOfBounds)?);
+        Ok(())
+    }
+
+    pub fn adjust_relative_base(&mut self, offset: W) -> Result<(), ExecutionError> {
+        let offset = narrow(&offset)?;
+        *self.relative_base = self
+            .overflow_policy
+            .add(*self.relative_base, offset)
+            .ok_or(ExecutionError::Overflow {
+                pc: self.pc(),
+                op: OverflowingOperation::AdjustRelativeBase,
+            })?;
+        Ok(())
+    }
+
+    /// Stops the program with [`ProgramState::Exited`], leaving the program
+    /// counter on this instruction.
+    pub fn exit(&mut self) {
+        self.effects.state = Some(ProgramState::Exited);
+    }
+
+    /// Reads the raw word of the next parameter, from the decode cache if the
+    /// instruction came from there.
+    fn raw_parameter(&self) -> Result<W, ExecutionError> {
+        let index = self.operands.read - 1;
+        Ok(match self.operands.cached {
+            Some(words) => W::from(words[index]),
+            None => self.get(self.operands.pc + 1 + index),
+        })
+    }
+
+    /// Resolves a position or relative mode parameter, remembering the address
+    /// in case it turns out to be invalid.
+    fn effective_address(&mut self, mode: ParameterMode, raw: &W) -> Result<usize, ExecutionError> {
+        let raw = narrow(raw)?;
+        let address = match mode {
+            ParameterMode::Relative => self.overflow_policy.add(*self.relative_base, raw).ok_or(
+                ExecutionError::Overflow {
+                    pc: self.pc(),
+                    op: OverflowingOperation::RelativeAddress,
+                },
+            )?,
+            _ => raw,
+        };
+        self.operands.address = Some(address);
+        usize::try_from(address).map_err(|_| ExecutionError::OutOfBounds)
+    }
+
+    fn get(&self, address: usize) -> W {
+        self.code
+            .get(address)
+            .cloned()
+            .unwrap_or_else(|| W::from(0))
+    }
+}
+
+impl<W: Word> BuiltinMachine<W> for Machine<'_, W> {
+    fn pc(&self) -> usize {
+        Machine::pc(self)
+    }
+
+    fn overflow_policy(&self) -> OverflowPolicy {
+        Machine::overflow_policy(self)
+    }
+
+    fn read(&mut self) -> Result<W, ExecutionError> {
+        Machine::read(self)
+    }
+
+    fn target(&mut self) -> Result<usize, ExecutionError> {
+        Machine::target(self)
+    }
+
+    fn write(&mut self, address: usize, value: W) -> Result<(), ExecutionError> {
+        Machine::write(self, address, value)
+    }
+
+    fn input(&mut self) -> Result<Option<W>, ExecutionError> {
+        Machine::input(self)
+    }
+
+    fn output(&mut self, value: W) {
+        Machine::output(self, value)
+    }
+
+    fn jump(&mut self, target: W) -> Result<(), ExecutionError> {
+        Machine::jump(self, target)
+    }
+
+    fn adjust_relative_base(&mut self, offset: W) -> Result<(), ExecutionError> {
+        Machine::adjust_relative_base(self, offset)
+    }
+
+    fn exit(&mut self) {
+        Machine::exit(self)
+    }
+}
+
+/// Lets [`Machine`] hold inputs of any type, sized or not.
+pub(super) struct DynInput<'a, I: ?Sized>(pub(super) &'a mut I);
+
+impl<W, I: Input<W> + ?Sized> Input<W> for DynInput<'_, I> {
+    fn read(&mut self) -> Option<W> {
+        self.0.read()
+    }
+}
+
+pub(super) struct DynOutput<'a, O: ?Sized>(pub(super) &'a mut O);
+
+impl<W, O: Output<W> + ?Sized> Output<W> for DynOutput<'_, O> {
+    fn write(&mut self, value: W) {
+        self.0.write(value)
+    }
+}
@@ -0,0 +1,256 @@
+use std::{collections::BTreeMap, fmt};
+
+use super::{
+    dialect::Dialect, ArithmeticOperation, Comparison, ExecutionError, Instruction, JumpCondition,
+    Opcode, ParameterMode, Program, Word,
+};
+
+/// An instruction parameter as written in the program text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Operand {
+    pub mode: ParameterMode,
+    pub value: i64,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            ParameterMode::Position => write!(f, "[{}]", self.value),
+            ParameterMode::Immediate => write!(f, "#{}", self.value),
+            ParameterMode::Relative if self.value < 0 => write!(f, "[rb{}]", self.value),
+            ParameterMode::Relative => write!(f, "[rb+{}]", self.value),
+        }
+    }
+}
+
+/// An instruction decoded from memory, without executing it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decoded {
+    pub opcode: Opcode,
+    pub operands: Vec<Operand>,
+}
+
+impl Decoded {
+    /// The number of words the instruction occupies.
+    pub fn word_count(&self) -> usize {
+        self.operands.len() + 1
+    }
+
+    /// The addresses execution may continue at after this instruction, as far
+    /// as they can be known without running the program.
+    pub fn successors(&self, address: usize) -> Vec<usize> {
+        let next = address + self.word_count();
+        match self.opcode {
+            Opcode::Exit => Vec::new(),
+            Opcode::Jump(cond) => {
+                let Operand { mode, value } = self.operands[0];
+                let taken = match (mode, cond) {
+                    (ParameterMode::Immediate, JumpCondition::True) => Some(value != 0),
+                    (ParameterMode::Immediate, JumpCondition::False) => Some(value == 0),
+                    _ => None,
+                };
+                let target = self.jump_target();
+
+                match (taken, target) {
+                    (Some(false), _) => vec![next],
+                    (Some(true), Some(target)) => vec![target],
+                    (Some(true), None) => Vec::new(),
+                    (None, Some(target)) => vec![next, target],
+                    (None, None) => vec![next],
+                }
+            }
+            _ => vec![next],
+        }
+    }
+
+    /// The destination of a jump whose target is an immediate value.
+    pub fn jump_target(&self) -> Option<usize> {
+        match (self.opcode, self.operands.get(1)) {
+            (
+                Opcode::Jump(_),
+                Some(Operand {
+                    mode: ParameterMode::Immediate,
+                    value,
+                }),
+            ) => usize::try_from(*value).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.opcode.mnemonic())?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{separator}{operand}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Opcode {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Arithmetic(ArithmeticOperation::Add) => "add",
+            Opcode::Arithmetic(ArithmeticOperation::Mul) => "mul",
+            Opcode::Store => "in",
+            Opcode::Print => "out",
+            Opcode::Jump(JumpCondition::True) => "jnz",
+            Opcode::Jump(JumpCondition::False) => "jz",
+            Opcode::Compare(Comparison::LessThan) => "lt",
+            Opcode::Compare(Comparison::Equals) => "eq",
+            Opcode::AdjustRelativeBase => "arb",
+            Opcode::Exit => "halt",
+            Opcode::Custom(custom) => custom.mnemonic(),
+        }
+    }
+}
+
+/// Decodes the instruction starting at `address` as `dialect` understands it.
+pub fn decode<W: Word>(
+    code: &[i64],
+    dialect: &Dialect<W>,
+    address: usize,
+) -> Result<Decoded, ExecutionError> {
+    let word = *code.get(address).ok_or(ExecutionError::OutOfBounds)?;
+    let Instruction { opcode, modes } = dialect.parse_instruction(word)?;
+
+    let mut operands = Vec::with_capacity(opcode.parameter_count());
+    for (i, &digit) in modes.iter().enumerate().take(opcode.parameter_count()) {
+        let mode = Program::parameter_mode(digit)?;
+        if mode == ParameterMode::Immediate && opcode.is_target(i) {
+            return Err(ExecutionError::InvalidImmediateParameter);
+        }
+        let value = *code
+            .get(address + 1 + i)
+            .ok_or(ExecutionError::OutOfBounds)?;
+        operands.push(Operand { mode, value });
+    }
+
+    Ok(Decoded { opcode, operands })
+}
+
+/// One line of a disassembly listing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Line {
+    Instruction {
+        address: usize,
+        words: Vec<i64>,
+        decoded: Decoded,
+    },
+    Data {
+        address: usize,
+        words: Vec<i64>,
+    },
+}
+
+impl Line {
+    pub fn address(&self) -> usize {
+        match self {
+            Line::Instruction { address, .. } | Line::Data { address, .. } => *address,
+        }
+    }
+
+    pub fn words(&self) -> &[i64] {
+        match self {
+            Line::Instruction { words, .. } | Line::Data { words, .. } => words,
+        }
+    }
+
+    /// The line in the syntax accepted by the assembler.
+    pub fn source(&self) -> String {
+        match self {
+            Line::Instruction { decoded, .. } => decoded.to_string(),
+            Line::Data { words, .. } => {
+                let words: Vec<String> = words.iter().map(i64::to_string).collect();
+                format!(".data {}", words.join(", "))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let words: Vec<String> = self.words().iter().map(i64::to_string).collect();
+        write!(
+            f,
+            "{:>6}: {:<24} {}",
+            self.address(),
+            words.join(","),
+            self.source()
+        )
+    }
+}
+
+const DATA_WORDS_PER_LINE: usize = 8;
+
+/// Finds every instruction reachable from address 0 by following the
+/// statically known control flow.
+pub fn reachable<W: Word>(code: &[i64], dialect: &Dialect<W>) -> BTreeMap<usize, Decoded> {
+    let mut found = BTreeMap::new();
+    let mut pending = vec![0];
+    while let Some(address) = pending.pop() {
+        if found.contains_key(&address) {
+            continue;
+        }
+        let Ok(decoded) = decode(code, dialect, address) else {
+            continue;
+        };
+        pending.extend(decoded.successors(address));
+        found.insert(address, decoded);
+    }
+    found
+}
+
+/// Produces a listing of `code` where reachable instructions are decoded and
+/// everything else is shown as data.
+pub fn disassemble<W: Word>(code: &[i64], dialect: &Dialect<W>) -> Vec<Line> {
+    let mut instructions = reachable(code, dialect);
+    let mut lines = Vec::new();
+    let mut address = 0;
+    while address < code.len() {
+        if let Some(decoded) = instructions.remove(&address) {
+            let len = decoded.word_count();
+            lines.push(Line::Instruction {
+                address,
+                words: code[address..address + len].to_vec(),
+                decoded,
+            });
+            address += len;
+            continue;
+        }
+
+        let start = address;
+        while address < code.len()
+            && address - start < DATA_WORDS_PER_LINE
+            && !instructions.contains_key(&address)
+        {
+            address += 1;
+        }
+        lines.push(Line::Data {
+            address: start,
+            words: code[start..address].to_vec(),
+        });
+    }
+    lines
+}
+
+/// The lines of the [`disassemble`] listing of `code` around `address`: the
+/// line containing it and up to `context` lines on either side.
+pub fn window<W: Word>(
+    code: &[i64],
+    dialect: &Dialect<W>,
+    address: usize,
+    context: usize,
+) -> Vec<Line> {
+    let lines = disassemble(code, dialect);
+    let Some(index) = lines
+        .iter()
+        .position(|line| address < line.address() + line.words().len())
+    else {
+        return Vec::new();
+    };
+    let end = (index + context + 1).min(lines.len());
+    lines[index.saturating_sub(context)..end].to_vec()
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead},
+    sync::mpsc::{Receiver, Sender},
+};
+
+use super::Word;
+
+/// A source of input values for [`super::Program::run`].
+///
+/// Returning `None` means that no input is available right now, which makes the
+/// program pause with [`super::ProgramState::ExpectingInput`].
+pub trait Input<W = i64> {
+    fn read(&mut self) -> Option<W>;
+}
+
+/// A sink for the values a program prints.
+pub trait Output<W = i64> {
+    fn write(&mut self, value: W);
+}
+
+impl<W, F: FnMut() -> Option<W>> Input<W> for F {
+    fn read(&mut self) -> Option<W> {
+        self()
+    }
+}
+
+impl<W, F: FnMut(W)> Output<W> for F {
+    fn write(&mut self, value: W) {
+        self(value)
+    }
+}
+
+impl<W> Input<W> for VecDeque<W> {
+    fn read(&mut self) -> Option<W> {
+        self.pop_front()
+    }
+}
+
+impl<W> Output<W> for VecDeque<W> {
+    fn write(&mut self, value: W) {
+        self.push_back(value)
+    }
+}
+
+impl<W> Output<W> for Vec<W> {
+    fn write(&mut self, value: W) {
+        self.push(value)
+    }
+}
+
+/// Blocks until a value arrives. A disconnected channel counts as missing input.
+impl<W> Input<W> for Receiver<W> {
+    fn read(&mut self) -> Option<W> {
+        self.recv().ok()
+    }
+}
+
+/// Values sent after the receiving end has been dropped are discarded.
+impl<W> Output<W> for Sender<W> {
+    fn write(&mut self, value: W) {
+        let _ = self.send(value);
+    }
+}
+
+/// Adapts any iterator over words into an [`Input`].
+pub struct Iter<I>(pub I);
+
+impl<W, I: Iterator<Item = W>> Input<W> for Iter<I> {
+    fn read(&mut self) -> Option<W> {
+        self.0.next()
+    }
+}
+
+/// Reads whitespace or comma separated numbers from standard input on demand.
+///
+/// End of file and tokens that are not numbers both end the input.
+#[derive(Default)]
+pub struct Stdin {
+    pending: VecDeque<String>,
+}
+
+impl Stdin {
+    pub fn new() -> Stdin {
+        Stdin::default()
+    }
+}
+
+impl<W: Word> Input<W> for Stdin {
+    fn read(&mut self) -> Option<W> {
+        while self.pending.is_empty() {
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            self.pending.extend(
+                line.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|token| !token.is_empty())
+                    .map(str::to_owned),
+            );
+        }
+
+        W::parse_word(&self.pending.pop_front()?).ok()
+    }
+}
+
+/// Prints every value on its own line of standard output.
+pub struct Stdout;
+
+impl<W: fmt::Display> Output<W> for Stdout {
+    fn write(&mut self, value: W) {
+        println!("{value}");
+    }
+}
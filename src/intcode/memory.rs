@@ -0,0 +1,418 @@
+//! Copy-on-write program memory.
+//!
+//! Memory is split into fixed-size pages that are shared between clones and
+//! only copied when one of the clones writes to them, so cloning a program is
+//! cheap no matter how much memory it uses. Words at far away addresses can
+//! be kept in a map instead, so that a single write to a huge address does not
+//! allocate everything below it; see [`Backend`].
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    iter::FusedIterator,
+    ops::{Index, IndexMut},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use super::Word;
+
+const PAGE_BITS: usize = 8;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+const OFFSET_MASK: usize = PAGE_SIZE - 1;
+
+/// How far past the end of the dense region a [`Backend::Hybrid`] memory still
+/// grows densely.
+const HYBRID_DENSE_GROWTH: usize = 1 << 16;
+
+type Page<W> = Arc<Vec<W>>;
+
+/// How many of the most recently written addresses a memory remembers.
+const RECENT_WRITES: usize = 4;
+
+/// Source of the ids that tell memories apart in [`Memory::version`].
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Where a [`Memory`] keeps its words.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Every word up to the highest written address is allocated.
+    Dense,
+    /// Only written words are stored, in an ordered map.
+    Sparse,
+    /// The loaded image and addresses close to it are dense, far away
+    /// addresses are sparse.
+    #[default]
+    Hybrid,
+}
+
+/// A growable array of words with O(1) clones.
+///
+/// Words between the end of the dense region and the end of the last page are
+/// always zero, and every sparse address lies past the dense region.
+pub struct Memory<W = i64> {
+    pages: Arc<Vec<Page<W>>>,
+    dense_len: usize,
+    sparse: Arc<BTreeMap<usize, W>>,
+    len: usize,
+    backend: Backend,
+    zero: W,
+    /// Unique to this memory; clones get a new one.
+    id: u64,
+    writes: u64,
+    recent: [usize; RECENT_WRITES],
+}
+
+impl<W> Memory<W> {
+    /// One past the highest address that has been loaded or written.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// The number of leading words that are stored densely.
+    pub fn dense_len(&self) -> usize {
+        self.dense_len
+    }
+
+    /// Identifies the contents of this memory: the version changes with every
+    /// write, and no two memories, not even clones, ever share a version.
+    pub(super) fn version(&self) -> (u64, u64) {
+        (self.id, self.writes)
+    }
+
+    /// The addresses written since this memory was at `version`, or `None`
+    /// if that is not known, because `version` belongs to another memory or
+    /// too much has been written since.
+    pub(super) fn written_since(
+        &self,
+        version: (u64, u64),
+    ) -> Option<impl Iterator<Item = usize> + '_> {
+        let (id, writes) = version;
+        if id != self.id || writes > self.writes || self.writes - writes > RECENT_WRITES as u64 {
+            return None;
+        }
+        Some((writes..self.writes).map(|w| self.recent[w as usize % RECENT_WRITES]))
+    }
+
+    /// Returns the word at `address`, or `None` if it is past the end.
+    pub fn get(&self, address: usize) -> Option<&W> {
+        if address >= self.len {
+            None
+        } else if address < self.dense_len {
+            Some(self.dense(address))
+        } else {
+            Some(self.sparse.get(&address).unwrap_or(&self.zero))
+        }
+    }
+
+    /// Iterates over every word up to [`Memory::len`], including the zeros
+    /// between sparse words. Use [`Memory::stored_words`] for memory that has
+    /// been written to at huge addresses.
+    pub fn iter(&self) -> Iter<'_, W> {
+        Iter {
+            memory: self,
+            address: 0,
+        }
+    }
+
+    /// Iterates over the words that are actually stored, in address order:
+    /// the whole dense region followed by every sparse word.
+    pub fn stored_words(&self) -> impl Iterator<Item = (usize, &W)> + '_ {
+        (0..self.dense_len)
+            .map(|address| (address, self.dense(address)))
+            .chain(self.sparse.iter().map(|(&address, word)| (address, word)))
+    }
+
+    fn dense(&self, address: usize) -> &W {
+        &self.pages[address >> PAGE_BITS][address & OFFSET_MASK]
+    }
+}
+
+impl<W: Word> Memory<W> {
+    pub fn new() -> Memory<W> {
+        Memory::with_backend(Vec::new(), Backend::default())
+    }
+
+    /// Loads `words` starting at address 0 into a memory using `backend`.
+    pub fn with_backend(mut words: Vec<W>, backend: Backend) -> Memory<W> {
+        let len = words.len();
+        let zero = W::from(0);
+        if backend == Backend::Sparse {
+            let sparse = words
+                .into_iter()
+                .enumerate()
+                .filter(|(_, word)| *word != zero)
+                .collect();
+            return Memory {
+                pages: Arc::new(Vec::new()),
+                dense_len: 0,
+                sparse: Arc::new(sparse),
+                len,
+                backend,
+                zero,
+                id: next_id(),
+                writes: 0,
+                recent: [0; RECENT_WRITES],
+            };
+        }
+
+        words.resize(len.div_ceil(PAGE_SIZE) * PAGE_SIZE, zero.clone());
+        let pages = words
+            .chunks(PAGE_SIZE)
+            .map(|page| Arc::new(page.to_vec()))
+            .collect();
+        Memory {
+            pages: Arc::new(pages),
+            dense_len: len,
+            sparse: Arc::new(BTreeMap::new()),
+            len,
+            backend,
+            zero,
+            id: next_id(),
+            writes: 0,
+            recent: [0; RECENT_WRITES],
+        }
+    }
+
+    /// Moves every stored word into a memory using `backend`.
+    pub fn set_backend(&mut self, backend: Backend) {
+        let mut rebuilt = Memory::with_backend(Vec::new(), backend);
+        for (address, word) in self.stored_words() {
+            *rebuilt.word_mut(address) = word.clone();
+        }
+        rebuilt.resize(self.len);
+        *self = rebuilt;
+    }
+
+    /// Grows memory to `len` words, which all read as zero. Only a
+    /// [`Backend::Dense`] memory allocates them. Memory never shrinks.
+    pub fn resize(&mut self, len: usize) {
+        if self.backend == Backend::Dense {
+            self.grow_dense(len);
+        }
+        self.len = self.len.max(len);
+    }
+
+    /// Returns the word at `address` for writing, growing memory with zeros
+    /// if necessary. Only the page containing the word is copied, and only if
+    /// it is shared with another clone.
+    pub fn word_mut(&mut self, address: usize) -> &mut W {
+        if address >= self.dense_len && self.grows_densely(address) {
+            self.grow_dense(address + 1);
+        }
+        self.len = self.len.max(address + 1);
+        self.recent[self.writes as usize % RECENT_WRITES] = address;
+        self.writes += 1;
+
+        if address < self.dense_len {
+            let pages = Arc::make_mut(&mut self.pages);
+            &mut Arc::make_mut(&mut pages[address >> PAGE_BITS])[address & OFFSET_MASK]
+        } else {
+            Arc::make_mut(&mut self.sparse)
+                .entry(address)
+                .or_insert_with(|| W::from(0))
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<W> {
+        self.iter().cloned().collect()
+    }
+
+    fn grows_densely(&self, address: usize) -> bool {
+        match self.backend {
+            Backend::Dense => true,
+            Backend::Sparse => false,
+            Backend::Hybrid => address - self.dense_len < HYBRID_DENSE_GROWTH,
+        }
+    }
+
+    /// Extends the dense region to `len` words, moving any sparse words it now
+    /// covers into pages.
+    fn grow_dense(&mut self, len: usize) {
+        if len <= self.dense_len {
+            return;
+        }
+        let needed = len.div_ceil(PAGE_SIZE);
+        if needed > self.pages.len() {
+            // New pages all share one zero page until they are written to.
+            let zero = Arc::new(vec![W::from(0); PAGE_SIZE]);
+            Arc::make_mut(&mut self.pages).resize(needed, zero);
+        }
+
+        let start = self.dense_len;
+        self.dense_len = len;
+        self.len = self.len.max(len);
+        if self.sparse.range(start..len).next().is_none() {
+            return;
+        }
+        let sparse = Arc::make_mut(&mut self.sparse);
+        let covered: Vec<usize> = sparse.range(start..len).map(|(&a, _)| a).collect();
+        let pages = Arc::make_mut(&mut self.pages);
+        for address in covered {
+            if let Some(word) = sparse.remove(&address) {
+                Arc::make_mut(&mut pages[address >> PAGE_BITS])[address & OFFSET_MASK] = word;
+            }
+        }
+    }
+}
+
+impl<W: Clone> Clone for Memory<W> {
+    fn clone(&self) -> Memory<W> {
+        Memory {
+            pages: self.pages.clone(),
+            dense_len: self.dense_len,
+            sparse: self.sparse.clone(),
+            len: self.len,
+            backend: self.backend,
+            zero: self.zero.clone(),
+            id: next_id(),
+            writes: 0,
+            recent: self.recent,
+        }
+    }
+}
+
+impl<W: Word> Default for Memory<W> {
+    fn default() -> Memory<W> {
+        Memory::new()
+    }
+}
+
+impl<W: Word> From<Vec<W>> for Memory<W> {
+    fn from(words: Vec<W>) -> Memory<W> {
+        Memory::with_backend(words, Backend::default())
+    }
+}
+
+impl<W: Word> FromIterator<W> for Memory<W> {
+    fn from_iter<I: IntoIterator<Item = W>>(iter: I) -> Memory<W> {
+        Memory::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl<W> Index<usize> for Memory<W> {
+    type Output = W;
+
+    fn index(&self, address: usize) -> &W {
+        match self.get(address) {
+            Some(word) => word,
+            None => panic!(
+                "address {address} is out of bounds for memory of length {}",
+                self.len
+            ),
+        }
+    }
+}
+
+/// Like indexing a `Vec`, this panics past the end; use
+/// [`Memory::word_mut`] to grow memory.
+impl<W: Word> IndexMut<usize> for Memory<W> {
+    fn index_mut(&mut self, address: usize) -> &mut W {
+        if address >= self.len {
+            panic!(
+                "address {address} is out of bounds for memory of length {}",
+                self.len
+            );
+        }
+        self.word_mut(address)
+    }
+}
+
+/// Pages that are still shared are known to be equal without looking at them.
+/// Words stored in only one of the memories are compared with what the other
+/// one reads there, so a zero written to a far address equals one that was
+/// never written.
+impl<W: PartialEq> PartialEq for Memory<W> {
+    fn eq(&self, other: &Memory<W>) -> bool {
+        if self.len != other.len {
+            return false;
+        }
+        if self.dense_len == other.dense_len {
+            let pages_equal = Arc::ptr_eq(&self.pages, &other.pages)
+                || self
+                    .pages
+                    .iter()
+                    .zip(other.pages.iter())
+                    .all(|(a, b)| Arc::ptr_eq(a, b) || a == b);
+            if !pages_equal {
+                return false;
+            }
+            if Arc::ptr_eq(&self.sparse, &other.sparse) || self.sparse == other.sparse {
+                return true;
+            }
+            let sparse_agrees = |a: &Memory<W>, b: &Memory<W>| {
+                a.sparse
+                    .iter()
+                    .all(|(&address, word)| b.get(address) == Some(word))
+            };
+            return sparse_agrees(self, other) && sparse_agrees(other, self);
+        }
+        let agrees = |a: &Memory<W>, b: &Memory<W>| {
+            a.stored_words()
+                .all(|(address, word)| b.get(address) == Some(word))
+        };
+        agrees(self, other) && agrees(other, self)
+    }
+}
+
+impl<W: Eq> Eq for Memory<W> {}
+
+/// Memory that extends past its dense region is shown as a map from address
+/// to word, holding only the words that are stored.
+impl<W: fmt::Debug> fmt::Debug for Memory<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.len == self.dense_len {
+            f.debug_list().entries(self.iter()).finish()
+        } else {
+            f.debug_map().entries(self.stored_words()).finish()
+        }
+    }
+}
+
+impl<'a, W> IntoIterator for &'a Memory<W> {
+    type Item = &'a W;
+    type IntoIter = Iter<'a, W>;
+
+    fn into_iter(self) -> Iter<'a, W> {
+        self.iter()
+    }
+}
+
+/// Iterates over the words of a [`Memory`] in address order.
+pub struct Iter<'a, W> {
+    memory: &'a Memory<W>,
+    address: usize,
+}
+
+impl<'a, W> Iterator for Iter<'a, W> {
+    type Item = &'a W;
+
+    fn next(&mut self) -> Option<&'a W> {
+        let word = self.memory.get(self.address)?;
+        self.address += 1;
+        Some(word)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.memory.len - self.address;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<W> ExactSizeIterator for Iter<'_, W> {}
+
+impl<W> FusedIterator for Iter<'_, W> {}
@@ -0,0 +1,375 @@
+//! Several programs wired together, each one's output feeding the input of
+//! others.
+//!
+//! Machines are connected with [`Network::connect`], which allows chains,
+//! rings and fan-out alike. A [`Network`] runs until every machine has exited
+//! or none of them can make progress, either round-robin on the calling thread
+//! or with every machine on a thread of its own.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Condvar, Mutex,
+    },
+    thread,
+};
+
+use thiserror::Error;
+
+use super::{Fault, Input, Output, Program, ProgramState, Word};
+
+pub mod packet;
+
+/// A machine that was still waiting for input when the network stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Waiting {
+    pub machine: usize,
+    pub pc: usize,
+}
+
+#[derive(Error, Debug)]
+pub enum NetworkError {
+    #[error("Machine {machine} faulted: {fault}")]
+    Fault { machine: usize, fault: Fault },
+    #[error("Machine {machine} used up its budget")]
+    BudgetExhausted { machine: usize },
+    #[error("Deadlock: {}", DeadlockList(.waiting))]
+    Deadlock { waiting: Vec<Waiting> },
+    #[error("Machine {machine} sent a packet to unknown address {address}")]
+    UnknownAddress { machine: usize, address: i64 },
+    #[error("Every machine has exited")]
+    Exited,
+}
+
+struct DeadlockList<'a>(&'a [Waiting]);
+
+impl fmt::Display for DeadlockList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, waiting) in self.0.iter().enumerate() {
+            let separator = if i == 0 { "" } else { ", " };
+            write!(
+                f,
+                "{separator}machine {} waits for input at pc {}",
+                waiting.machine, waiting.pc
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct Machine<W: Word> {
+    program: Program<W>,
+    inbox: VecDeque<W>,
+    outputs: Vec<W>,
+    targets: Vec<usize>,
+    state: Option<ProgramState>,
+}
+
+/// Programs connected output to input.
+#[derive(Clone)]
+pub struct Network<W: Word = i64> {
+    machines: Vec<Machine<W>>,
+}
+
+impl<W: Word> Network<W> {
+    pub fn new() -> Network<W> {
+        Network {
+            machines: Vec::new(),
+        }
+    }
+
+    /// Adds a machine running `program` and returns its id.
+    pub fn add(&mut self, program: Program<W>) -> usize {
+        self.machines.push(Machine {
+            program,
+            inbox: VecDeque::new(),
+            outputs: Vec::new(),
+            targets: Vec::new(),
+            state: None,
+        });
+        self.machines.len() - 1
+    }
+
+    /// Sends every value machine `from` prints to machine `to` as input. A
+    /// machine connected to several others sends each of them every value.
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.machines[from].targets.push(to);
+    }
+
+    /// Queues `value` as input to `machine`, behind anything already queued.
+    pub fn feed(&mut self, machine: usize, value: W) {
+        self.machines[machine].inbox.push_back(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+
+    pub fn program(&self, machine: usize) -> &Program<W> {
+        &self.machines[machine].program
+    }
+
+    /// Every value `machine` has printed so far, whether or not it is
+    /// connected to another machine.
+    pub fn outputs(&self, machine: usize) -> &[W] {
+        &self.machines[machine].outputs
+    }
+
+    /// The state `machine` stopped in the last time it ran, or `None` if it
+    /// has not run yet.
+    pub fn state(&self, machine: usize) -> Option<ProgramState> {
+        self.machines[machine].state
+    }
+
+    /// Runs the machines round-robin on this thread, each until it exits or
+    /// runs out of input, until all have exited or a whole round passes
+    /// without any of them executing an instruction.
+    pub fn run(&mut self) -> Result<(), NetworkError> {
+        loop {
+            let mut progress = false;
+            for id in 0..self.machines.len() {
+                let machine = &mut self.machines[id];
+                if machine.state == Some(ProgramState::Exited) {
+                    continue;
+                }
+                let executed = machine.program.executed();
+                let mut output = Vec::new();
+                let state = machine
+                    .program
+                    .run(&mut machine.inbox, &mut output)
+                    .map_err(|fault| NetworkError::Fault { machine: id, fault })?;
+                machine.state = Some(state);
+                progress |= machine.program.executed() != executed;
+                self.deliver(id, output);
+                if state == ProgramState::BudgetExhausted {
+                    return Err(NetworkError::BudgetExhausted { machine: id });
+                }
+            }
+
+            if self.all_exited() {
+                return Ok(());
+            }
+            if !progress {
+                return Err(self.deadlock());
+            }
+        }
+    }
+
+    fn deliver(&mut self, from: usize, output: Vec<W>) {
+        for i in 0..self.machines[from].targets.len() {
+            let to = self.machines[from].targets[i];
+            self.machines[to].inbox.extend(output.iter().cloned());
+        }
+        self.machines[from].outputs.extend(output);
+    }
+
+    fn all_exited(&self) -> bool {
+        self.machines
+            .iter()
+            .all(|machine| machine.state == Some(ProgramState::Exited))
+    }
+
+    fn deadlock(&self) -> NetworkError {
+        let waiting = self
+            .machines
+            .iter()
+            .enumerate()
+            .filter(|(_, machine)| machine.state != Some(ProgramState::Exited))
+            .map(|(machine, m)| Waiting {
+                machine,
+                pc: m.program.program_counter(),
+            })
+            .collect();
+        NetworkError::Deadlock { waiting }
+    }
+}
+
+impl<W: Word + Send + Sync> Network<W> {
+    /// Runs every machine that has not exited on a thread of its own, passing
+    /// values over channels. A deadlock is detected once every running machine
+    /// waits for input and no values are on their way to any of them.
+    pub fn run_threaded(&mut self) -> Result<(), NetworkError> {
+        let (senders, receivers): (Vec<Sender<W>>, Vec<Receiver<W>>) =
+            self.machines.iter().map(|_| mpsc::channel()).unzip();
+        let exited: Vec<bool> = self
+            .machines
+            .iter()
+            .map(|machine| machine.state == Some(ProgramState::Exited))
+            .collect();
+        let mut pending = vec![0; self.machines.len()];
+        for (id, machine) in self.machines.iter_mut().enumerate() {
+            pending[id] = machine.inbox.len();
+            for value in machine.inbox.drain(..) {
+                let _ = senders[id].send(value);
+            }
+        }
+        let shared = Shared {
+            state: Mutex::new(SharedState {
+                live: exited.iter().filter(|&&e| !e).count(),
+                blocked: 0,
+                pending,
+                exited,
+                deadlocked: false,
+            }),
+            wake: Condvar::new(),
+        };
+
+        let results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .machines
+                .iter_mut()
+                .zip(receivers)
+                .enumerate()
+                .map(|(id, (machine, receiver))| {
+                    let targets: Vec<(usize, Sender<W>)> = machine
+                        .targets
+                        .iter()
+                        .map(|&to| (to, senders[to].clone()))
+                        .collect();
+                    let shared = &shared;
+                    scope.spawn(move || {
+                        let mut input = ChannelInput {
+                            id,
+                            receiver,
+                            shared,
+                        };
+                        let mut output = ChannelOutput {
+                            targets,
+                            shared,
+                            outputs: Vec::new(),
+                        };
+                        let result = if machine.state == Some(ProgramState::Exited) {
+                            Ok(ProgramState::Exited)
+                        } else {
+                            let result = machine.program.run(&mut input, &mut output);
+                            shared.exit(id);
+                            result
+                        };
+                        machine.outputs.append(&mut output.outputs);
+                        (result, input.receiver)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("machine thread panicked"))
+                .collect()
+        });
+
+        // Values sent after a machine stopped are still waiting in its
+        // channel; they become its input for the next run.
+        for (machine, (_, receiver)) in self.machines.iter_mut().zip(&results) {
+            machine.inbox.extend(receiver.try_iter());
+        }
+        for (id, (result, _)) in results.into_iter().enumerate() {
+            let state = result.map_err(|fault| NetworkError::Fault { machine: id, fault })?;
+            self.machines[id].state = Some(state);
+            if state == ProgramState::BudgetExhausted {
+                return Err(NetworkError::BudgetExhausted { machine: id });
+            }
+        }
+        if self.all_exited() {
+            Ok(())
+        } else {
+            Err(self.deadlock())
+        }
+    }
+}
+
+impl<W: Word> Default for Network<W> {
+    fn default() -> Network<W> {
+        Network::new()
+    }
+}
+
+/// Bookkeeping shared by the threads of [`Network::run_threaded`].
+struct Shared {
+    state: Mutex<SharedState>,
+    /// Signalled whenever a value is sent or a machine stops.
+    wake: Condvar,
+}
+
+struct SharedState {
+    live: usize,
+    blocked: usize,
+    /// Values sent to each machine that it has not read yet.
+    pending: Vec<usize>,
+    exited: Vec<bool>,
+    deadlocked: bool,
+}
+
+impl SharedState {
+    fn is_deadlocked(&self) -> bool {
+        self.blocked == self.live
+            && self
+                .pending
+                .iter()
+                .zip(&self.exited)
+                .all(|(&pending, &exited)| exited || pending == 0)
+    }
+}
+
+impl Shared {
+    fn exit(&self, id: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.live -= 1;
+        state.exited[id] = true;
+        self.wake.notify_all();
+    }
+}
+
+struct ChannelInput<'a, W> {
+    id: usize,
+    receiver: Receiver<W>,
+    shared: &'a Shared,
+}
+
+impl<W> Input<W> for ChannelInput<'_, W> {
+    fn read(&mut self) -> Option<W> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.blocked += 1;
+        loop {
+            if let Ok(value) = self.receiver.try_recv() {
+                state.pending[self.id] -= 1;
+                state.blocked -= 1;
+                return Some(value);
+            }
+            if !state.deadlocked && state.is_deadlocked() {
+                state.deadlocked = true;
+                self.shared.wake.notify_all();
+            }
+            if state.deadlocked {
+                state.blocked -= 1;
+                return None;
+            }
+            state = self.shared.wake.wait(state).unwrap();
+        }
+    }
+}
+
+struct ChannelOutput<'a, W> {
+    targets: Vec<(usize, Sender<W>)>,
+    shared: &'a Shared,
+    outputs: Vec<W>,
+}
+
+impl<W: Clone> Output<W> for ChannelOutput<'_, W> {
+    fn write(&mut self, value: W) {
+        let mut state = self.shared.state.lock().unwrap();
+        for (to, sender) in &self.targets {
+            if !state.exited[*to] {
+                state.pending[*to] += 1;
+            }
+            let _ = sender.send(value.clone());
+        }
+        self.outputs.push(value);
+        self.shared.wake.notify_all();
+    }
+}
@@ -0,0 +1,158 @@
+//! A packet-switched network of identical machines.
+//!
+//! Every machine is booted with its address as its first input and then sends
+//! packets by printing three values: the destination address followed by the
+//! packet's `x` and `y`. A machine asking for input while no packet is queued
+//! for it reads `-1`. Packets sent to [`NAT`] are held back, and once the
+//! whole network goes idle the last of them is sent on to machine 0.
+
+use std::{collections::VecDeque, mem};
+
+use super::NetworkError;
+use crate::intcode::{Program, ProgramState};
+
+/// The address of the NAT.
+pub const NAT: usize = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub destination: usize,
+    pub x: i64,
+    pub y: i64,
+}
+
+/// What happened during one [`PacketNetwork::round`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Round {
+    /// Every packet sent, in the order they were sent.
+    pub sent: Vec<Packet>,
+    /// Whether no machine had a packet waiting and none sent one.
+    pub idle: bool,
+    /// The packet the NAT sent to machine 0 because the network was idle.
+    pub woken: Option<Packet>,
+}
+
+#[derive(Clone)]
+struct Nic {
+    program: Program,
+    inbox: VecDeque<i64>,
+    /// Output that does not make up a whole packet yet.
+    partial: Vec<i64>,
+    state: Option<ProgramState>,
+}
+
+/// Machines connected by a packet-switched network, run one after the other
+/// on the calling thread so that every run is reproducible.
+#[derive(Clone)]
+pub struct PacketNetwork {
+    nics: Vec<Nic>,
+    nat: Option<Packet>,
+}
+
+impl PacketNetwork {
+    /// Boots `size` copies of `program` at addresses `0..size`.
+    pub fn boot(program: &Program, size: usize) -> PacketNetwork {
+        let nics = (0..size)
+            .map(|address| Nic {
+                program: program.clone(),
+                inbox: VecDeque::from([address as i64]),
+                partial: Vec::new(),
+                state: None,
+            })
+            .collect();
+        PacketNetwork { nics, nat: None }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nics.is_empty()
+    }
+
+    /// The last packet sent to the NAT.
+    pub fn nat(&self) -> Option<Packet> {
+        self.nat
+    }
+
+    /// Lets every machine run in address order until it asks for input with
+    /// nothing queued, which it is answered once with `-1` before it pauses.
+    /// Packets are delivered as soon as they are sent, so machines later in
+    /// the round already see them. If the round was idle, the NAT then sends
+    /// its packet to machine 0.
+    pub fn round(&mut self) -> Result<Round, NetworkError> {
+        if !self.nics.is_empty()
+            && self
+                .nics
+                .iter()
+                .all(|nic| nic.state == Some(ProgramState::Exited))
+        {
+            return Err(NetworkError::Exited);
+        }
+
+        let mut round = Round {
+            idle: true,
+            ..Round::default()
+        };
+        for id in 0..self.nics.len() {
+            let nic = &mut self.nics[id];
+            if nic.state == Some(ProgramState::Exited) {
+                continue;
+            }
+            round.idle &= nic.inbox.is_empty();
+
+            let inbox = &mut nic.inbox;
+            let mut polled = false;
+            let mut input = || {
+                inbox
+                    .pop_front()
+                    .or_else(|| (!mem::replace(&mut polled, true)).then_some(-1))
+            };
+            let state = nic
+                .program
+                .run(&mut input, &mut nic.partial)
+                .map_err(|fault| NetworkError::Fault { machine: id, fault })?;
+            nic.state = Some(state);
+
+            let complete = nic.partial.len() - nic.partial.len() % 3;
+            let output: Vec<i64> = nic.partial.drain(..complete).collect();
+            for words in output.chunks(3) {
+                let packet = Packet {
+                    destination: usize::try_from(words[0])
+                        .ok()
+                        .filter(|&a| a < self.nics.len() || a == NAT)
+                        .ok_or(NetworkError::UnknownAddress {
+                            machine: id,
+                            address: words[0],
+                        })?,
+                    x: words[1],
+                    y: words[2],
+                };
+                self.send(packet);
+                round.sent.push(packet);
+            }
+        }
+
+        round.idle &= round.sent.is_empty();
+        if round.idle {
+            if let Some(packet) = self.nat {
+                let packet = Packet {
+                    destination: 0,
+                    ..packet
+                };
+                self.send(packet);
+                round.woken = Some(packet);
+            }
+        }
+        Ok(round)
+    }
+
+    fn send(&mut self, packet: Packet) {
+        if packet.destination == NAT {
+            self.nat = Some(packet);
+        } else if let Some(nic) = self.nics.get_mut(packet.destination) {
+            nic.inbox.extend([packet.x, packet.y]);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Counting where a program spends its time and which code it reaches.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    ops::Range,
+};
+
+use super::{dialect::Dialect, disasm, Opcode, Step, Word};
+
+/// How often a conditional jump did and did not jump. A jump to the following
+/// instruction counts as not taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Execution counts collected by [`super::Program::start_profile`]. Unlike
+/// [`super::Program::executed`], these include the instruction the program
+/// exited at, so that it does not show up as unreached code.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Executions per instruction address.
+    pub executions: BTreeMap<usize, u64>,
+    pub opcodes: HashMap<Opcode, u64>,
+    /// Parameter reads per memory address. Immediate parameters are not
+    /// counted.
+    pub reads: BTreeMap<usize, u64>,
+    pub writes: BTreeMap<usize, u64>,
+    /// Outcomes per jump instruction address.
+    pub branches: BTreeMap<usize, Branch>,
+    /// Taken jumps to the same or an earlier address, keyed by the address of
+    /// the jump and its target.
+    pub back_edges: BTreeMap<(usize, usize), u64>,
+}
+
+impl Profile {
+    pub(super) fn record<W>(&mut self, step: &Step<W>) {
+        *self.executions.entry(step.pc).or_default() += 1;
+        *self.opcodes.entry(step.opcode).or_default() += 1;
+        for parameter in &step.parameters {
+            if let (Some(address), Some(_)) = (parameter.address, &parameter.value) {
+                *self.reads.entry(address).or_default() += 1;
+            }
+        }
+        if let Some(write) = &step.write {
+            *self.writes.entry(write.address).or_default() += 1;
+        }
+
+        if let Opcode::Jump(_) = step.opcode {
+            let branch = self.branches.entry(step.pc).or_default();
+            if step.next_pc == step.pc + 1 + step.opcode.parameter_count() {
+                branch.not_taken += 1;
+            } else {
+                branch.taken += 1;
+                if step.next_pc <= step.pc {
+                    *self.back_edges.entry((step.pc, step.next_pc)).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    /// The total number of instructions executed.
+    pub fn instructions(&self) -> u64 {
+        self.executions.values().sum()
+    }
+
+    /// Summarizes the profile of a run of `code`, which should be the program
+    /// as it was loaded, in `dialect`, listing at most `max_loops` hot loops.
+    pub fn report<W: Word>(&self, code: &[i64], dialect: &Dialect<W>, max_loops: usize) -> Report {
+        let mut opcodes: Vec<_> = self.opcodes.iter().map(|(&op, &n)| (op, n)).collect();
+        opcodes.sort_by_key(|&(op, n)| (std::cmp::Reverse(n), op.code()));
+
+        let mut hot_loops: Vec<HotLoop> = self
+            .back_edges
+            .iter()
+            .map(|(&(jump, start), &iterations)| HotLoop {
+                start,
+                jump,
+                iterations,
+                instructions: self.executions.range(start..=jump).map(|(_, n)| n).sum(),
+            })
+            .collect();
+        hot_loops.sort_by_key(|l| (std::cmp::Reverse(l.instructions), l.start));
+        hot_loops.truncate(max_loops);
+
+        let one_sided = self
+            .branches
+            .iter()
+            .filter(|(_, b)| b.taken == 0 || b.not_taken == 0)
+            .map(|(&address, &branch)| (address, branch))
+            .collect();
+
+        let mut unexecuted: Vec<Range<usize>> = Vec::new();
+        for (address, decoded) in disasm::reachable(code, dialect) {
+            if self.executions.contains_key(&address) {
+                continue;
+            }
+            let end = address + decoded.word_count();
+            match unexecuted.last_mut() {
+                Some(range) if range.end == address => range.end = end,
+                _ => unexecuted.push(address..end),
+            }
+        }
+
+        Report {
+            instructions: self.instructions(),
+            opcodes,
+            hot_loops,
+            one_sided,
+            unexecuted,
+        }
+    }
+}
+
+/// A range of code that was repeated by jumping back from its end to its
+/// start.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HotLoop {
+    pub start: usize,
+    /// Address of the jump instruction closing the loop.
+    pub jump: usize,
+    /// How often the jump went back to `start`.
+    pub iterations: u64,
+    /// Instructions executed between `start` and `jump`, inclusive.
+    pub instructions: u64,
+}
+
+/// The result of [`Profile::report`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub instructions: u64,
+    /// Executions per opcode, most frequent first.
+    pub opcodes: Vec<(Opcode, u64)>,
+    /// The loops executing the most instructions, hottest first.
+    pub hot_loops: Vec<HotLoop>,
+    /// Conditional jumps that always or never jumped.
+    pub one_sided: Vec<(usize, Branch)>,
+    /// Statically reachable code that never ran.
+    pub unexecuted: Vec<Range<usize>>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} instructions executed", self.instructions)?;
+        for (opcode, count) in &self.opcodes {
+            writeln!(f, "  {:<6} {count}", opcode.mnemonic())?;
+        }
+
+        writeln!(f, "Hot loops:")?;
+        for l in &self.hot_loops {
+            writeln!(
+                f,
+                "  {:>6}..={:<6} {} iterations, {} instructions",
+                l.start, l.jump, l.iterations, l.instructions
+            )?;
+        }
+
+        writeln!(f, "One-sided branches:")?;
+        for (address, branch) in &self.one_sided {
+            let outcome = if branch.taken == 0 { "never" } else { "always" };
+            let count = branch.taken + branch.not_taken;
+            writeln!(f, "  {address:>6}: {outcome} jumped ({count} times)")?;
+        }
+
+        write!(f, "Never executed:")?;
+        for range in &self.unexecuted {
+            write!(f, "\n  {:>6}..{}", range.start, range.end)?;
+        }
+        Ok(())
+    }
+}
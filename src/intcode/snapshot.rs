@@ -0,0 +1,231 @@
+//! Saving and restoring the complete state of a [`Program`].
+//!
+//! A snapshot starts with a magic header and a version byte, followed by the
+//! program counter, the relative base, the memory and the pending input and
+//! output queues, all written as varints.
+//!
+//! Since version 2, memory is stored as its backend, its length, the dense
+//! words and the sparse `(address, word)` pairs. Version 1 snapshots, which
+//! store memory as a plain list of words, can still be read.
+//!
+//! Since version 3, the relative base is followed by the number of executed
+//! instructions, the [`OverflowPolicy`] and the [`InputMode`]. Older
+//! snapshots restore with none executed and the default policy and mode.
+//!
+//! The [`Dialect`] and attached devices are not saved. A program with a
+//! custom dialect has to be restored with [`Program::restore_with_dialect`],
+//! and devices have to be attached again.
+
+use std::collections::VecDeque;
+
+use thiserror::Error;
+
+use super::{
+    dialect::Dialect,
+    memory::{Backend, Memory},
+    varint, InputMode, OverflowPolicy, Program,
+};
+
+const MAGIC: &[u8; 4] = b"ICSN";
+const VERSION: u8 = 3;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    #[error("Not a snapshot")]
+    BadMagic,
+    #[error("Unsupported snapshot version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Snapshot is truncated or corrupt")]
+    Corrupt,
+}
+
+/// A program together with the I/O that was in flight when it was saved.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub program: Program,
+    /// Input that was queued but not yet read.
+    pub input: VecDeque<i64>,
+    /// Output that was produced but not yet consumed.
+    pub output: Vec<i64>,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(&self.program, &self.input, &self.output)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        let mut bytes = bytes.iter().copied();
+        if !bytes.by_ref().take(MAGIC.len()).eq(MAGIC.iter().copied()) {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = bytes.next().ok_or(SnapshotError::Corrupt)?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let program_counter = read_address(&mut bytes)?;
+        let relative_base = read_signed(&mut bytes)?;
+        let (executed, overflow_policy, input_mode) = if version >= 3 {
+            (
+                read_unsigned(&mut bytes)?,
+                read_overflow_policy(&mut bytes)?,
+                read_input_mode(&mut bytes)?,
+            )
+        } else {
+            Default::default()
+        };
+        let code = if version == 1 {
+            Memory::from(read_words(&mut bytes)?)
+        } else {
+            read_memory(&mut bytes)?
+        };
+        let input = read_words(&mut bytes)?.into();
+        let output = read_words(&mut bytes)?;
+        if bytes.next().is_some() {
+            return Err(SnapshotError::Corrupt);
+        }
+
+        let mut program = Program::from_code(Vec::new());
+        program.code = code;
+        program.program_counter = program_counter;
+        program.relative_base = relative_base;
+        program.executed = executed;
+        program.overflow_policy = overflow_policy;
+        program.input_mode = input_mode;
+        Ok(Snapshot {
+            program,
+            input,
+            output,
+        })
+    }
+}
+
+impl Program {
+    /// Serializes the program's memory and registers. Any trace being recorded
+    /// is not included.
+    pub fn snapshot(&self) -> Vec<u8> {
+        encode(self, &VecDeque::new(), &[])
+    }
+
+    /// Recreates a program from the output of [`Program::snapshot`] or
+    /// [`Snapshot::to_bytes`], dropping any saved I/O.
+    pub fn restore(bytes: &[u8]) -> Result<Program, SnapshotError> {
+        Ok(Snapshot::from_bytes(bytes)?.program)
+    }
+
+    /// Like [`Program::restore`], for a program that was running with
+    /// `dialect`.
+    pub fn restore_with_dialect(bytes: &[u8], dialect: &Dialect) -> Result<Program, SnapshotError> {
+        let mut program = Program::restore(bytes)?;
+        program.set_dialect(dialect.clone());
+        Ok(program)
+    }
+}
+
+fn encode(program: &Program, input: &VecDeque<i64>, output: &[i64]) -> Vec<u8> {
+    let mut buf = MAGIC.to_vec();
+    buf.push(VERSION);
+    varint::put_unsigned(&mut buf, program.program_counter as u64);
+    varint::put_signed(&mut buf, program.relative_base);
+    varint::put_unsigned(&mut buf, program.executed);
+    buf.push(match program.overflow_policy {
+        OverflowPolicy::Checked => 0,
+        OverflowPolicy::Wrapping => 1,
+        OverflowPolicy::Saturating => 2,
+    });
+    buf.push(match program.input_mode {
+        InputMode::Pause => 0,
+        InputMode::Strict => 1,
+    });
+    write_memory(&mut buf, &program.code);
+    write_words(&mut buf, input.iter().copied());
+    write_words(&mut buf, output.iter().copied());
+    buf
+}
+
+fn write_memory(buf: &mut Vec<u8>, memory: &Memory) {
+    buf.push(match memory.backend() {
+        Backend::Dense => 0,
+        Backend::Sparse => 1,
+        Backend::Hybrid => 2,
+    });
+    varint::put_unsigned(buf, memory.len() as u64);
+    let dense = memory.dense_len();
+    write_words(buf, memory.iter().take(dense).copied());
+    let sparse: Vec<_> = memory.stored_words().skip(dense).collect();
+    varint::put_unsigned(buf, sparse.len() as u64);
+    for (address, &word) in sparse {
+        varint::put_unsigned(buf, address as u64);
+        varint::put_signed(buf, word);
+    }
+}
+
+fn read_memory(bytes: &mut impl Iterator<Item = u8>) -> Result<Memory, SnapshotError> {
+    let backend = match bytes.next() {
+        Some(0) => Backend::Dense,
+        Some(1) => Backend::Sparse,
+        Some(2) => Backend::Hybrid,
+        _ => return Err(SnapshotError::Corrupt),
+    };
+    let len = read_address(bytes)?;
+    let words = read_words(bytes)?;
+    // A dense memory stores every word up to its length, so its length is
+    // bounded by the size of the snapshot.
+    if words.len() > len || (backend == Backend::Dense && words.len() != len) {
+        return Err(SnapshotError::Corrupt);
+    }
+    let mut memory = Memory::with_backend(words, backend);
+    for _ in 0..read_unsigned(bytes)? {
+        let address = read_address(bytes)?;
+        if address < memory.dense_len() || address >= len {
+            return Err(SnapshotError::Corrupt);
+        }
+        *memory.word_mut(address) = read_signed(bytes)?;
+    }
+    memory.resize(len);
+    Ok(memory)
+}
+
+fn read_overflow_policy(
+    bytes: &mut impl Iterator<Item = u8>,
+) -> Result<OverflowPolicy, SnapshotError> {
+    match bytes.next() {
+        Some(0) => Ok(OverflowPolicy::Checked),
+        Some(1) => Ok(OverflowPolicy::Wrapping),
+        Some(2) => Ok(OverflowPolicy::Saturating),
+        _ => Err(SnapshotError::Corrupt),
+    }
+}
+
+fn read_input_mode(bytes: &mut impl Iterator<Item = u8>) -> Result<InputMode, SnapshotError> {
+    match bytes.next() {
+        Some(0) => Ok(InputMode::Pause),
+        Some(1) => Ok(InputMode::Strict),
+        _ => Err(SnapshotError::Corrupt),
+    }
+}
+
+fn write_words(buf: &mut Vec<u8>, words: impl ExactSizeIterator<Item = i64>) {
+    varint::put_unsigned(buf, words.len() as u64);
+    for word in words {
+        varint::put_signed(buf, word);
+    }
+}
+
+fn read_words(bytes: &mut impl Iterator<Item = u8>) -> Result<Vec<i64>, SnapshotError> {
+    let len = read_unsigned(bytes)?;
+    (0..len).map(|_| read_signed(bytes)).collect()
+}
+
+fn read_address(bytes: &mut impl Iterator<Item = u8>) -> Result<usize, SnapshotError> {
+    usize::try_from(read_unsigned(bytes)?).map_err(|_| SnapshotError::Corrupt)
+}
+
+fn read_unsigned(bytes: &mut impl Iterator<Item = u8>) -> Result<u64, SnapshotError> {
+    varint::get_unsigned(bytes).ok_or(SnapshotError::Corrupt)
+}
+
+fn read_signed(bytes: &mut impl Iterator<Item = u8>) -> Result<i64, SnapshotError> {
+    varint::get_signed(bytes).ok_or(SnapshotError::Corrupt)
+}
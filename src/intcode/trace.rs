@@ -0,0 +1,317 @@
+//! Recording, replaying and comparing instruction traces.
+//!
+//! Traces are stored in a compact binary format: a magic header and version
+//! byte, followed by one record per executed instruction. Every number is
+//! written as a varint. Since version 2, records hold the number of operands
+//! read, as operations need not read all of their parameters; version 1
+//! traces are still read, assuming every parameter that is not a target was
+//! read.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+use thiserror::Error;
+
+use super::{dialect::Dialect, varint, Fault, MemoryWrite, Opcode, Program, Step};
+
+const MAGIC: &[u8; 4] = b"ICTR";
+const VERSION: u8 = 2;
+
+const HAS_WRITE: u8 = 1;
+const HAS_INPUT: u8 = 2;
+const HAS_OUTPUT: u8 = 4;
+
+/// The observable effect of one executed instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry<W = i64> {
+    pub pc: usize,
+    pub opcode: Opcode,
+    /// The values of all parameters that were read.
+    pub operands: Vec<W>,
+    pub write: Option<MemoryWrite<W>>,
+    pub input: Option<W>,
+    pub output: Option<W>,
+}
+
+impl<W: Clone> From<&Step<W>> for TraceEntry<W> {
+    fn from(step: &Step<W>) -> TraceEntry<W> {
+        TraceEntry {
+            pc: step.pc,
+            opcode: step.opcode,
+            operands: step
+                .parameters
+                .iter()
+                .filter_map(|p| p.value.clone())
+                .collect(),
+            write: step.write.clone(),
+            input: step.input.clone(),
+            output: step.output.clone(),
+        }
+    }
+}
+
+impl<W: fmt::Display> fmt::Display for TraceEntry<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>6}: {}", self.pc, self.opcode.mnemonic())?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{separator}{operand}")?;
+        }
+        if let Some(MemoryWrite { address, value }) = &self.write {
+            write!(f, " -> [{address}] = {value}")?;
+        }
+        if let Some(value) = &self.input {
+            write!(f, " (input {value})")?;
+        }
+        if let Some(value) = &self.output {
+            write!(f, " (output {value})")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TraceError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Not a trace file")]
+    BadMagic,
+    #[error("Unsupported trace version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Trace file is truncated or corrupt")]
+    Corrupt,
+    #[error(transparent)]
+    Execution(#[from] Fault),
+    #[error(
+        "Replay diverged from the trace at step {index}: expected `{expected}`, got `{actual}`"
+    )]
+    Diverged {
+        index: usize,
+        expected: Box<TraceEntry>,
+        actual: Box<TraceEntry>,
+    },
+}
+
+/// A recording of the instructions a program executed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trace<W = i64> {
+    pub entries: Vec<TraceEntry<W>>,
+}
+
+impl<W> Default for Trace<W> {
+    fn default() -> Trace<W> {
+        Trace {
+            entries: Vec::new(),
+        }
+    }
+}
+
+/// The binary format only holds words that fit into an `i64`.
+impl Trace {
+    pub fn write_to(&self, mut w: impl Write) -> io::Result<()> {
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        for entry in &self.entries {
+            let mut flags = 0;
+            if entry.write.is_some() {
+                flags |= HAS_WRITE;
+            }
+            if entry.input.is_some() {
+                flags |= HAS_INPUT;
+            }
+            if entry.output.is_some() {
+                flags |= HAS_OUTPUT;
+            }
+            buf.push(flags);
+            varint::put_unsigned(&mut buf, entry.pc as u64);
+            varint::put_unsigned(&mut buf, entry.opcode.code() as u64);
+            varint::put_unsigned(&mut buf, entry.operands.len() as u64);
+            for &operand in &entry.operands {
+                varint::put_signed(&mut buf, operand);
+            }
+            if let Some(MemoryWrite { address, value }) = entry.write {
+                varint::put_unsigned(&mut buf, address as u64);
+                varint::put_signed(&mut buf, value);
+            }
+            if let Some(value) = entry.input {
+                varint::put_signed(&mut buf, value);
+            }
+            if let Some(value) = entry.output {
+                varint::put_signed(&mut buf, value);
+            }
+        }
+        w.write_all(&buf)
+    }
+
+    /// Reads a trace of a program using the standard dialect.
+    pub fn read_from(r: impl Read) -> Result<Trace, TraceError> {
+        Trace::read_with_dialect(r, &Dialect::standard())
+    }
+
+    /// Reads a trace of a program using `dialect`, which tells what the
+    /// recorded opcodes stand for and how many operands they read.
+    pub fn read_with_dialect(mut r: impl Read, dialect: &Dialect) -> Result<Trace, TraceError> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        let mut bytes = data.into_iter();
+
+        if !bytes.by_ref().take(MAGIC.len()).eq(MAGIC.iter().copied()) {
+            return Err(TraceError::BadMagic);
+        }
+        let version = match bytes.next() {
+            Some(version @ (1 | VERSION)) => version,
+            Some(version) => return Err(TraceError::UnsupportedVersion(version)),
+            None => return Err(TraceError::Corrupt),
+        };
+
+        let mut entries = Vec::new();
+        while let Some(flags) = bytes.next() {
+            let pc = read_unsigned(&mut bytes)?
+                .try_into()
+                .map_err(|_| TraceError::Corrupt)?;
+            let code = read_unsigned(&mut bytes)?
+                .try_into()
+                .map_err(|_| TraceError::Corrupt)?;
+            let opcode = dialect.opcode(code).map_err(|_| TraceError::Corrupt)?;
+            let reads = if version == 1 {
+                (0..opcode.parameter_count())
+                    .filter(|&parameter| !opcode.is_target(parameter))
+                    .count()
+            } else {
+                usize::try_from(read_unsigned(&mut bytes)?)
+                    .ok()
+                    .filter(|&reads| reads <= opcode.parameter_count())
+                    .ok_or(TraceError::Corrupt)?
+            };
+            let operands = (0..reads)
+                .map(|_| read_signed(&mut bytes))
+                .collect::<Result<_, _>>()?;
+            let write = if flags & HAS_WRITE != 0 {
+                Some(MemoryWrite {
+                    address: read_unsigned(&mut bytes)?
+                        .try_into()
+                        .map_err(|_| TraceError::Corrupt)?,
+                    value: read_signed(&mut bytes)?,
+                })
+            } else {
+                None
+            };
+            let input = if flags & HAS_INPUT != 0 {
+                Some(read_signed(&mut bytes)?)
+            } else {
+                None
+            };
+            let output = if flags & HAS_OUTPUT != 0 {
+                Some(read_signed(&mut bytes)?)
+            } else {
+                None
+            };
+            entries.push(TraceEntry {
+                pc,
+                opcode,
+                operands,
+                write,
+                input,
+                output,
+            });
+        }
+
+        Ok(Trace { entries })
+    }
+}
+
+impl<W: Clone> Trace<W> {
+    /// All values the program read, in order.
+    pub fn inputs(&self) -> impl Iterator<Item = W> + '_ {
+        self.entries.iter().filter_map(|e| e.input.clone())
+    }
+
+    /// All values the program printed, in order.
+    pub fn outputs(&self) -> impl Iterator<Item = W> + '_ {
+        self.entries.iter().filter_map(|e| e.output.clone())
+    }
+}
+
+/// The first point at which two traces disagree.
+#[derive(Debug)]
+pub struct Divergence<'a, W = i64> {
+    pub index: usize,
+    pub left: Option<&'a TraceEntry<W>>,
+    pub right: Option<&'a TraceEntry<W>>,
+}
+
+/// Finds the first step at which `left` and `right` differ, including one trace
+/// ending before the other.
+pub fn diff<'a, W: PartialEq>(
+    left: &'a Trace<W>,
+    right: &'a Trace<W>,
+) -> Option<Divergence<'a, W>> {
+    let len = left.entries.len().max(right.entries.len());
+    (0..len).find_map(|index| {
+        let l = left.entries.get(index);
+        let r = right.entries.get(index);
+        (l != r).then_some(Divergence {
+            index,
+            left: l,
+            right: r,
+        })
+    })
+}
+
+/// Re-executes a program against a trace, feeding it the recorded inputs and
+/// checking every step against the recording.
+pub struct Replay<'a> {
+    program: Program,
+    trace: &'a Trace,
+    index: usize,
+}
+
+impl<'a> Replay<'a> {
+    /// `program` must be in the state it was in when the trace was started.
+    pub fn new(program: Program, trace: &'a Trace) -> Replay<'a> {
+        Replay {
+            program,
+            trace,
+            index: 0,
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+}
+
+impl Iterator for Replay<'_> {
+    type Item = Result<Step, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let expected = self.trace.entries.get(self.index)?;
+        let mut input = expected.input;
+        let step = match self.program.step(&mut || input.take(), &mut Vec::new()) {
+            Ok(step) => step,
+            Err(err) => return Some(Err(err.into())),
+        };
+
+        let actual = TraceEntry::from(&step);
+        if step.state.is_some() || actual != *expected {
+            return Some(Err(TraceError::Diverged {
+                index: self.index,
+                expected: Box::new(expected.clone()),
+                actual: Box::new(actual),
+            }));
+        }
+
+        self.index += 1;
+        Some(Ok(step))
+    }
+}
+
+fn read_unsigned(bytes: &mut impl Iterator<Item = u8>) -> Result<u64, TraceError> {
+    varint::get_unsigned(bytes).ok_or(TraceError::Corrupt)
+}
+
+fn read_signed(bytes: &mut impl Iterator<Item = u8>) -> Result<i64, TraceError> {
+    varint::get_signed(bytes).ok_or(TraceError::Corrupt)
+}
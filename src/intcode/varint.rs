@@ -0,0 +1,35 @@
+//! LEB128 variable-length integers shared by the binary file formats.
+
+pub fn put_unsigned(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Zigzag-encodes `value` so that small negative numbers stay short.
+pub fn put_signed(buf: &mut Vec<u8>, value: i64) {
+    put_unsigned(buf, ((value << 1) ^ (value >> 63)) as u64)
+}
+
+pub fn get_unsigned(bytes: &mut impl Iterator<Item = u8>) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = bytes.next()?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+pub fn get_signed(bytes: &mut impl Iterator<Item = u8>) -> Option<i64> {
+    let value = get_unsigned(bytes)?;
+    Some((value >> 1) as i64 ^ -((value & 1) as i64))
+}
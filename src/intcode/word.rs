@@ -0,0 +1,59 @@
+//! The numbers a [`Program`](super::Program) computes with.
+
+use std::fmt;
+
+#[cfg(feature = "bigint")]
+pub use num_bigint::BigInt;
+
+use super::{OverflowPolicy, ParseError};
+
+/// A memory word. Instructions, addresses and relative base offsets must fit
+/// into an `i64`, but the values a program computes with need not.
+pub trait Word: Clone + Eq + Ord + fmt::Debug + fmt::Display + From<i64> {
+    fn parse_word(s: &str) -> Result<Self, ParseError>;
+
+    /// Narrows the word, returning `None` if it does not fit.
+    fn to_i64(&self) -> Option<i64>;
+
+    fn add(&self, rhs: &Self, policy: OverflowPolicy) -> Option<Self>;
+
+    fn mul(&self, rhs: &Self, policy: OverflowPolicy) -> Option<Self>;
+}
+
+impl Word for i64 {
+    fn parse_word(s: &str) -> Result<i64, ParseError> {
+        Ok(s.parse()?)
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        Some(*self)
+    }
+
+    fn add(&self, rhs: &i64, policy: OverflowPolicy) -> Option<i64> {
+        policy.add(*self, *rhs)
+    }
+
+    fn mul(&self, rhs: &i64, policy: OverflowPolicy) -> Option<i64> {
+        policy.mul(*self, *rhs)
+    }
+}
+
+/// Arbitrary-precision words never overflow, so the policy is irrelevant.
+#[cfg(feature = "bigint")]
+impl Word for BigInt {
+    fn parse_word(s: &str) -> Result<BigInt, ParseError> {
+        s.parse().map_err(|_| ParseError::InvalidWord(s.to_owned()))
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        i64::try_from(self).ok()
+    }
+
+    fn add(&self, rhs: &BigInt, _: OverflowPolicy) -> Option<BigInt> {
+        Some(self + rhs)
+    }
+
+    fn mul(&self, rhs: &BigInt, _: OverflowPolicy) -> Option<BigInt> {
+        Some(self * rhs)
+    }
+}
@@ -1,13 +1,201 @@
 use anyhow::{Error, Result};
-use aoc19::days::{day1, day2, day3, day4, day5, day6, day7, day8};
+use aoc19::{
+    days::{day1, day2, day23, day3, day4, day5, day6, day7, day8, day9},
+    intcode::{
+        ascii::Ascii,
+        asm,
+        cfg::Cfg,
+        cli::RunArgs,
+        debugger::Debugger,
+        disasm,
+        io::{Iter, Stdin, Stdout},
+        trace::{self, Replay, Trace, TraceEntry},
+        Input, Program, ProgramState,
+    },
+};
 use std::{
     env,
-    io::{self, Read},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write},
 };
 
+fn load_program(path: &str) -> Result<Program> {
+    Ok(fs::read_to_string(path)?.trim().parse()?)
+}
+
+fn load_trace(path: &str) -> Result<Trace> {
+    Ok(Trace::read_from(BufReader::new(File::open(path)?))?)
+}
+
+/// Feeds `lines` to an ASCII program whenever it asks for input, printing
+/// everything it writes.
+fn run_ascii(
+    ascii: &mut Ascii,
+    mut lines: impl Iterator<Item = io::Result<String>>,
+) -> Result<ProgramState> {
+    loop {
+        let state = ascii.run()?;
+        for line in ascii.take_lines() {
+            println!("{line}");
+        }
+        for value in ascii.take_values() {
+            println!("{value}");
+        }
+        print!("{}", ascii.take_partial_line());
+        io::stdout().flush()?;
+
+        if state != ProgramState::ExpectingInput {
+            return Ok(state);
+        }
+        match lines.next() {
+            Some(line) => ascii.send_line(&line?),
+            None => return Ok(state),
+        }
+    }
+}
+
+/// `intcode run [--ascii] [--input-file <path>] <program> [inputs...]`, see
+/// [`aoc19::intcode::cli`].
+fn run(args: &[String]) -> Result<()> {
+    let mut args = RunArgs::parse(args)?;
+    let interactive = args.is_interactive();
+    let prompt = interactive && io::stdin().is_terminal();
+    if let Some(file) = &args.input_file {
+        let contents = fs::read_to_string(file)?;
+        args.add_inputs(&contents);
+    }
+
+    let mut program = load_program(&args.program)?;
+    let state = if args.ascii {
+        let mut ascii = Ascii::new(program);
+        let state = if interactive {
+            run_ascii(&mut ascii, io::stdin().lock().lines())?
+        } else {
+            run_ascii(&mut ascii, args.inputs.into_iter().map(Ok))?
+        };
+        program = ascii.program().clone();
+        state
+    } else if interactive {
+        let mut stdin = Stdin::new();
+        let mut read = || {
+            if prompt {
+                eprint!("input> ");
+            }
+            stdin.read()
+        };
+        program.run(&mut read, &mut Stdout)?
+    } else {
+        program.run(&mut Iter(args.values()?.into_iter()), &mut Stdout)?
+    };
+
+    let executed = program.executed();
+    match state {
+        ProgramState::Exited => eprintln!("Halted after {executed} instructions"),
+        _ => eprintln!(
+            "Blocked ({state:?}) at {} after {executed} instructions",
+            program.program_counter()
+        ),
+    }
+    Ok(())
+}
+
+fn intcode(args: &[String]) -> Result<()> {
+    let command = args.first().ok_or(Error::msg("Missing intcode command"))?;
+    let arg = |i: usize| {
+        args.get(i)
+            .map(String::as_str)
+            .ok_or(Error::msg("Missing argument"))
+    };
+
+    match command.as_str() {
+        "asm" => println!("{}", asm::assemble_program(&fs::read_to_string(arg(1)?)?)?),
+        "debug" => Debugger::new(load_program(arg(1)?)?).repl(io::stdin().lock(), io::stdout())?,
+        "cfg" => {
+            let (dot, path) = match arg(1)? {
+                "--dot" => (true, arg(2)?),
+                path => (false, path),
+            };
+            let program = load_program(path)?;
+            let cfg = Cfg::build(&program.code.to_vec(), program.dialect());
+            if dot {
+                print!("{}", cfg.to_dot());
+            } else {
+                print!("{cfg}");
+            }
+        }
+        "disasm" => {
+            let program = load_program(arg(1)?)?;
+            for line in disasm::disassemble(&program.code.to_vec(), program.dialect()) {
+                println!("{line}");
+            }
+        }
+        "trace" => {
+            let mut program = load_program(arg(1)?)?;
+            let trace_path = arg(2)?;
+            let input: Vec<i64> = args
+                .get(3..)
+                .unwrap_or_default()
+                .iter()
+                .map(|s| s.parse())
+                .collect::<Result<_, _>>()?;
+            program.start_trace();
+            let result = program.execute(&input);
+            let trace = program.take_trace().unwrap_or_default();
+            trace.write_to(BufWriter::new(File::create(trace_path)?))?;
+            let (state, output) = result?;
+            for value in output {
+                println!("{value}");
+            }
+            eprintln!("{state:?} after {} instructions", trace.entries.len());
+        }
+        "run" => run(&args[1..])?,
+        "profile" => {
+            let mut program = load_program(arg(1)?)?;
+            let code = program.code.to_vec();
+            let input: Vec<i64> = args[2..]
+                .iter()
+                .map(|s| s.parse())
+                .collect::<Result<_, _>>()?;
+            program.start_profile();
+            let (_, output) = program.execute(&input)?;
+            for value in output {
+                println!("{value}");
+            }
+            let profile = program.take_profile().unwrap_or_default();
+            println!("{}", profile.report(&code, program.dialect(), 10));
+        }
+        "replay" => {
+            let trace = load_trace(arg(2)?)?;
+            for step in Replay::new(load_program(arg(1)?)?, &trace) {
+                println!("{}", TraceEntry::from(&step?));
+            }
+        }
+        "trace-diff" => {
+            let (left, right) = (load_trace(arg(1)?)?, load_trace(arg(2)?)?);
+            match trace::diff(&left, &right) {
+                None => println!("Traces are identical"),
+                Some(divergence) => {
+                    let show = |entry: Option<&TraceEntry>| {
+                        entry.map_or("<end of trace>".to_owned(), TraceEntry::to_string)
+                    };
+                    println!("Traces diverge at step {}", divergence.index);
+                    println!("< {}", show(divergence.left));
+                    println!("> {}", show(divergence.right));
+                }
+            }
+        }
+        _ => return Err(Error::msg("Unknown intcode command")),
+    }
+
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let args: Vec<String> = env::args().collect();
     let day = args.get(1).ok_or(Error::msg("Missing day"))?.as_str();
+    if day == "intcode" {
+        return intcode(&args[2..]);
+    }
     let part = args.get(2).ok_or(Error::msg("Missing part"))?.as_str();
 
     let mut input = String::new();
@@ -31,6 +219,10 @@ fn main() -> Result<()> {
         ("7", "b") => day7::part_b(inp),
         ("8", "a") => day8::part_a(inp),
         ("8", "b") => day8::part_b(inp),
+        ("9", "a") => day9::part_a(inp),
+        ("9", "b") => day9::part_b(inp),
+        ("23", "a") => day23::part_a(inp),
+        ("23", "b") => day23::part_b(inp),
         _ => Err(Error::msg("Unknown day/part combination")),
     }?;
 
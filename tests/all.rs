@@ -33,3 +33,5 @@ test! {day5}
 test! {day6}
 test! {day7}
 test! {day8}
+test! {day9}
+test! {day23}
@@ -0,0 +1,1021 @@
+use aoc19::intcode::{
+    ascii::{self, Ascii},
+    asm,
+    cfg::{Cfg, EdgeKind},
+    cli::{ArgsError, RunArgs},
+    debugger::{Debugger, Stop, Watch},
+    device::{CycleCounter, Device, DeviceError, Framebuffer, Random},
+    dialect::{CustomOperation, Dialect, DialectError},
+    disasm, io,
+    memory::Backend,
+    network::{
+        packet::{Packet, PacketNetwork, NAT},
+        Network, NetworkError, Waiting,
+    },
+    profile,
+    snapshot::{Snapshot, SnapshotError},
+    trace::{self, Replay, Trace},
+    ArithmeticOperation, Budget, ExecutionError, InputMode, Memory, MemoryWrite, Opcode,
+    OverflowPolicy, OverflowingOperation, ParameterMode, Program, ProgramState,
+};
+use std::{collections::VecDeque, sync::mpsc, time::Duration};
+
+const QUINE: &str = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+
+fn parse(s: &str) -> Program {
+    s.parse().unwrap()
+}
+
+#[test]
+fn relative_mode_quine() {
+    let mut p = parse(QUINE);
+    let (state, output) = p.execute(&[]).unwrap();
+    assert_eq!(state, ProgramState::Exited);
+    assert_eq!(output, parse(QUINE).code.to_vec());
+}
+
+#[test]
+fn relative_base_adjusts_and_addresses() {
+    let mut p = parse("109,3,109,-1,204,0,99");
+    assert_eq!(p.execute(&[]).unwrap().1, [109]);
+    assert_eq!(p.relative_base(), 2);
+}
+
+#[test]
+fn memory_reads_zero_past_end_and_grows_on_write() {
+    let mut p = parse("4,1000,99");
+    assert_eq!(p.execute(&[]).unwrap().1, [0]);
+    assert_eq!(p.code.len(), 3);
+
+    let mut p = parse("109,100,21101,3,4,-50,204,-50,99");
+    assert_eq!(p.execute(&[]).unwrap().1, [7]);
+    assert_eq!(p.code.len(), 51);
+    assert_eq!(p.code[50], 7);
+    assert_eq!(p.code[20], 0);
+}
+
+#[test]
+fn run_with_queue_pauses_and_resumes() {
+    let mut p = parse("3,11,3,12,1,11,12,13,4,13,99");
+    let mut input = VecDeque::from([20]);
+    let mut output = Vec::new();
+    assert_eq!(
+        p.run(&mut input, &mut output).unwrap(),
+        ProgramState::ExpectingInput
+    );
+    input.push_back(22);
+    assert_eq!(
+        p.run(&mut input, &mut output).unwrap(),
+        ProgramState::Exited
+    );
+    assert_eq!(output, [42]);
+}
+
+#[test]
+fn run_over_channels() {
+    let (in_tx, mut in_rx) = mpsc::channel();
+    let (mut out_tx, out_rx) = mpsc::channel();
+    in_tx.send(7).unwrap();
+    drop(in_tx);
+    let mut p = parse("3,0,4,0,99");
+    assert_eq!(
+        p.run(&mut in_rx, &mut out_tx).unwrap(),
+        ProgramState::Exited
+    );
+    assert_eq!(out_rx.recv().unwrap(), 7);
+}
+
+#[test]
+fn step_reports_decoded_instruction() {
+    let mut p = parse("1002,4,3,4,33");
+    let step = p
+        .step(&mut io::Iter(std::iter::empty()), &mut Vec::new())
+        .unwrap();
+    assert_eq!(step.pc, 0);
+    assert_eq!(step.opcode, Opcode::Arithmetic(ArithmeticOperation::Mul));
+    assert_eq!(step.parameters[0].address, Some(4));
+    assert_eq!(step.parameters[0].value, Some(33));
+    assert_eq!(step.parameters[1].mode, ParameterMode::Immediate);
+    assert_eq!(
+        step.write,
+        Some(MemoryWrite {
+            address: 4,
+            value: 99
+        })
+    );
+    assert_eq!(step.next_pc, 4);
+    assert_eq!(p.program_counter(), 4);
+
+    let step = p
+        .step(&mut io::Iter(std::iter::empty()), &mut Vec::new())
+        .unwrap();
+    assert_eq!(step.state, Some(ProgramState::Exited));
+    assert_eq!(p.program_counter(), 4);
+}
+
+#[test]
+fn debugger_stops_on_breakpoint_and_watchpoint() {
+    let mut dbg = Debugger::new(parse("3,11,1001,11,1,11,4,11,99"));
+    dbg.push_input(41);
+    dbg.add_breakpoint(2);
+    dbg.add_watchpoint(11, Watch::Read);
+    assert!(matches!(dbg.resume_until_stop(), Stop::Breakpoint(2)));
+    assert!(matches!(
+        dbg.resume_until_stop(),
+        Stop::Watchpoint {
+            address: 11,
+            written: true
+        }
+    ));
+    assert!(matches!(
+        dbg.resume_until_stop(),
+        Stop::Watchpoint {
+            address: 11,
+            written: false
+        }
+    ));
+    assert!(matches!(dbg.resume_until_stop(), Stop::Exited));
+    assert_eq!(dbg.take_output(), [42]);
+}
+
+#[test]
+fn debugger_shows_the_programs_executed_count() {
+    let mut p = parse("1101,1,1,7,3,7,99,0");
+    assert_eq!(p.execute(&[]).unwrap().0, ProgramState::ExpectingInput);
+    let mut dbg = Debugger::new(p);
+    dbg.push_input(5);
+    assert!(matches!(dbg.resume_until_stop(), Stop::Exited));
+    let mut out = Vec::new();
+    dbg.command("regs", &mut out).unwrap();
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .contains("executed      2\n"));
+}
+
+#[test]
+fn debugger_dumps_memory_up_to_the_end_of_the_address_space() {
+    let mut dbg = Debugger::new(parse("99"));
+    let mut out = Vec::new();
+    let max = usize::MAX;
+    dbg.command(&format!("mem {} 100", max - 2), &mut out)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        format!("{}: 0 0\n", max - 2)
+    );
+
+    let mut out = Vec::new();
+    dbg.command(&format!("mem 0 {max}"), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.lines().count(), 4096 / 8 + 1);
+    assert!(out.ends_with("(only the first 4096 words are shown)\n"));
+}
+
+#[test]
+fn disassembly_skips_unreachable_data() {
+    let code = parse("1105,1,4,98,204,-3,99").code.to_vec();
+    let listing: Vec<String> = disasm::disassemble(&code, &Dialect::<i64>::standard())
+        .iter()
+        .map(disasm::Line::source)
+        .collect();
+    assert_eq!(listing, ["jnz #1, #4", ".data 98", "out [rb-3]", "halt"]);
+}
+
+const DOUBLER: &str = "
+        arb #x
+loop:   in [rb]             ; read a number
+        jz [rb], #end
+        mul [rb], #2, [rb + 1]
+        out [x+1]
+        jnz #1, #loop
+end:    halt
+x:      .data 0, end-1
+";
+
+#[test]
+fn assembled_program_runs() {
+    let mut p = asm::assemble_program(DOUBLER).unwrap();
+    assert_eq!(p.code[18], 15);
+    assert_eq!(
+        p.execute(&[3, 21, 0]).unwrap(),
+        (ProgramState::Exited, vec![6, 42])
+    );
+    assert_eq!(p.code[18], 42);
+}
+
+#[test]
+fn assembly_round_trips_through_disassembly() {
+    let code = asm::assemble(DOUBLER).unwrap();
+    let source: Vec<String> = disasm::disassemble(&code, &Dialect::<i64>::standard())
+        .iter()
+        .map(disasm::Line::source)
+        .collect();
+    assert_eq!(asm::assemble(&source.join("\n")).unwrap(), code);
+    assert_eq!(
+        parse(&Program::from_code(code.clone()).to_string())
+            .code
+            .to_vec(),
+        code
+    );
+}
+
+#[test]
+fn assembler_reports_errors() {
+    assert_eq!(
+        asm::assemble("add #1, #2, #3").unwrap_err(),
+        asm::AsmError::ImmediateTarget { line: 1 }
+    );
+    assert!(matches!(
+        asm::assemble("\njnz #1, #nowhere").unwrap_err(),
+        asm::AsmError::UndefinedLabel { line: 2, .. }
+    ));
+}
+
+#[test]
+fn trace_round_trips_and_replays() {
+    let mut p = parse("3,9,8,9,10,9,4,9,99,-1,8");
+    p.start_trace();
+    p.execute(&[8]).unwrap();
+    let recorded = p.take_trace().unwrap();
+    assert_eq!(recorded.outputs().collect::<Vec<_>>(), [1]);
+
+    let mut bytes = Vec::new();
+    recorded.write_to(&mut bytes).unwrap();
+    let loaded = Trace::read_from(bytes.as_slice()).unwrap();
+    assert_eq!(loaded, recorded);
+
+    let replayed: Result<Vec<_>, _> =
+        Replay::new(parse("3,9,8,9,10,9,4,9,99,-1,8"), &loaded).collect();
+    assert_eq!(replayed.unwrap().len(), recorded.entries.len());
+
+    let mut q = parse("3,9,8,9,10,9,4,9,99,-1,8");
+    q.start_trace();
+    q.execute(&[7]).unwrap();
+    let other = q.take_trace().unwrap();
+    assert_eq!(trace::diff(&recorded, &other).unwrap().index, 0);
+    assert!(trace::diff(&recorded, &recorded).is_none());
+}
+
+#[test]
+fn traces_record_how_many_operands_were_read() {
+    let mut dialect = Dialect::standard();
+    // Prints its second parameter unless the first is zero.
+    let print_unless_zero = CustomOperation::new("outnz", 2, &[], |machine| {
+        let condition: i64 = machine.read()?;
+        if condition != 0 {
+            let value = machine.read()?;
+            machine.output(value);
+        }
+        Ok(())
+    });
+    dialect.register(10, print_unless_zero).unwrap();
+    let mut p = parse("1110,0,5,1110,1,6,99");
+    p.set_dialect(dialect.clone());
+    p.start_trace();
+    assert_eq!(p.execute(&[]).unwrap().1, [6]);
+    let recorded = p.take_trace().unwrap();
+    let mut bytes = Vec::new();
+    recorded.write_to(&mut bytes).unwrap();
+    let loaded = Trace::read_with_dialect(bytes.as_slice(), &dialect).unwrap();
+    assert_eq!(loaded, recorded);
+    assert_eq!(loaded.entries[0].operands, [0]);
+    assert_eq!(loaded.entries[1].operands, [1, 6]);
+
+    // Version 1 did not store the number of operands: `out #42`, `halt`.
+    let version1 = b"ICTR\x01\x04\x00\x04\x54\x54\x00\x02\x63";
+    let loaded = Trace::read_from(&version1[..]).unwrap();
+    assert_eq!(loaded.entries.len(), 2);
+    assert_eq!(
+        (
+            loaded.entries[0].operands.as_slice(),
+            loaded.entries[0].output
+        ),
+        (&[42][..], Some(42))
+    );
+    assert_eq!(loaded.entries[1].opcode, Opcode::Exit);
+}
+
+#[test]
+fn snapshot_resumes_where_it_left_off() {
+    let mut p = parse(QUINE);
+    for _ in 0..10 {
+        p.step(&mut io::Iter(std::iter::empty()), &mut Vec::new())
+            .unwrap();
+    }
+    let mut restored = Program::restore(&p.snapshot()).unwrap();
+    assert_eq!(restored.program_counter(), p.program_counter());
+    assert_eq!(restored.relative_base(), p.relative_base());
+    assert_eq!(restored.executed(), 10);
+    assert_eq!(restored.execute(&[]).unwrap(), p.execute(&[]).unwrap());
+
+    let mut p = parse("1101,9223372036854775807,1,9,3,0,4,9,99,0");
+    p.set_overflow_policy(OverflowPolicy::Wrapping);
+    p.set_input_mode(InputMode::Strict);
+    let mut restored = Program::restore(&p.snapshot()).unwrap();
+    let fault = restored.execute(&[]).unwrap_err();
+    assert!(matches!(
+        fault.error,
+        ExecutionError::UnexpectedEndOfInput { pc: 4 }
+    ));
+    assert_eq!(restored.code[9], i64::MIN);
+
+    let saved = Snapshot {
+        program: parse("3,0,99"),
+        input: VecDeque::from([-5]),
+        output: vec![1, 2],
+    };
+    let loaded = Snapshot::from_bytes(&saved.to_bytes()).unwrap();
+    assert_eq!((loaded.input, loaded.output), (saved.input, saved.output));
+    assert_eq!(
+        Program::restore(b"ICSN\x07").err(),
+        Some(SnapshotError::UnsupportedVersion(7))
+    );
+}
+
+#[test]
+fn corrupt_snapshot_memory_is_rejected() {
+    // Version 2 with pc 0 and relative base 0, followed by memory.
+    let restore = |memory: &[u8]| Program::restore(&[b"ICSN\x02\0\0", memory].concat());
+    let max_address = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+
+    // A hybrid memory of length 3 holding 1 densely and 7 at address 2.
+    assert!(restore(&[2, 3, 1, 2, 1, 2, 14, 0, 0]).is_ok());
+    // The sparse address is inside the dense region or past the end.
+    assert_eq!(
+        restore(&[2, 3, 1, 2, 1, 0, 14, 0, 0]).err(),
+        Some(SnapshotError::Corrupt)
+    );
+    assert_eq!(
+        restore(&[2, 3, 1, 2, 1, 3, 14, 0, 0]).err(),
+        Some(SnapshotError::Corrupt)
+    );
+    assert_eq!(
+        restore(&[&[2, 3, 1, 2, 1][..], &max_address, &[14, 0, 0]].concat()).err(),
+        Some(SnapshotError::Corrupt)
+    );
+    // A dense memory claims far more words than it stores.
+    assert_eq!(
+        restore(&[&[0][..], &max_address, &[1, 2, 0, 0, 0]].concat()).err(),
+        Some(SnapshotError::Corrupt)
+    );
+    assert_eq!(
+        restore(&[0, 3, 1, 2, 1, 2, 14, 0, 0]).err(),
+        Some(SnapshotError::Corrupt)
+    );
+}
+
+#[test]
+fn budget_stops_runaway_programs() {
+    let spin = asm::assemble_program("loop: add [x], #1, [x]\n jnz #1, #loop\nx: .data 0").unwrap();
+
+    let mut p = spin.clone();
+    p.set_budget(Budget {
+        instructions: Some(10),
+        ..Budget::default()
+    });
+    assert_eq!(p.execute(&[]).unwrap().0, ProgramState::BudgetExhausted);
+    assert_eq!(p.code[7], 5);
+    p.execute(&[]).unwrap();
+    assert_eq!(p.code[7], 10);
+
+    let mut p = spin;
+    p.set_budget(Budget {
+        time: Some(Duration::from_millis(10)),
+        detect_loops: true,
+        ..Budget::default()
+    });
+    assert_eq!(p.execute(&[]).unwrap().0, ProgramState::BudgetExhausted);
+
+    let mut stuck = asm::assemble_program("loop: jz #0, #loop").unwrap();
+    stuck.set_budget(Budget {
+        detect_loops: true,
+        ..Budget::default()
+    });
+    assert!(matches!(
+        stuck.execute(&[]).map_err(|fault| fault.error),
+        Err(ExecutionError::InfiniteLoop { pc: 0 })
+    ));
+}
+
+#[test]
+fn overflow_policy_applies_to_words_and_relative_base() {
+    let big = i64::MAX.to_string();
+    let mul = parse(&format!("1002,7,2,7,4,7,99,{big}"));
+
+    let mut p = mul.clone();
+    assert!(matches!(
+        p.execute(&[]).map_err(|fault| fault.error),
+        Err(ExecutionError::Overflow {
+            pc: 0,
+            op: OverflowingOperation::Mul
+        })
+    ));
+
+    let mut p = mul.clone();
+    p.set_overflow_policy(OverflowPolicy::Wrapping);
+    assert_eq!(p.execute(&[]).unwrap().1, [-2]);
+
+    let mut p = mul;
+    p.set_overflow_policy(OverflowPolicy::Saturating);
+    assert_eq!(p.execute(&[]).unwrap().1, [i64::MAX]);
+
+    let mut p = parse(&format!("109,{big},109,1,99"));
+    assert!(matches!(
+        p.execute(&[]).map_err(|fault| fault.error),
+        Err(ExecutionError::Overflow {
+            pc: 2,
+            op: OverflowingOperation::AdjustRelativeBase
+        })
+    ));
+}
+
+#[test]
+fn cloned_memory_is_copied_on_write() {
+    let original = Memory::from((0..3000).collect::<Vec<i64>>());
+    let mut copy = original.clone();
+    assert_eq!(copy, original);
+    copy[2500] = -1;
+    *copy.word_mut(5000) = 7;
+    assert_ne!(copy, original);
+    assert_eq!((original[2500], original.len()), (2500, 3000));
+    assert_eq!((copy[2500], copy.len()), (-1, 5001));
+    assert_eq!(copy.get(4000), Some(&0));
+    assert_eq!(copy.iter().len(), 5001);
+
+    let mut p = parse("1001,7,1,7,4,7,99,41");
+    let q = p.clone();
+    p.execute(&[]).unwrap();
+    assert_eq!(q.code[7], 41);
+    assert_eq!(p.code[7], 42);
+}
+
+#[test]
+fn far_writes_are_stored_sparsely() {
+    let far = 1_000_000_000_000usize;
+    let mut p = parse(&format!("1102,6,7,{far},4,{far},99"));
+    assert_eq!(p.execute(&[]).unwrap().1, [42]);
+    assert_eq!(p.memory().len(), far + 1);
+    assert_eq!(p.memory().dense_len(), 7);
+    assert_eq!(p.memory().get(far - 1), Some(&0));
+    assert_eq!(p.to_string(), format!("1102,6,7,{far},4,{far},99,{far}:42"));
+    assert_eq!(
+        format!("{:?}", p.memory()),
+        format!("{{0: 1102, 1: 6, 2: 7, 3: {far}, 4: 4, 5: {far}, 6: 99, {far}: 42}}")
+    );
+
+    let restored = Program::restore(&p.snapshot()).unwrap();
+    assert_eq!(restored.memory(), p.memory());
+    assert_eq!(restored.memory().backend(), Backend::Hybrid);
+
+    let mut sparse = parse("1101,20,22,100,4,100,99");
+    sparse.set_memory_backend(Backend::Sparse);
+    assert_eq!(sparse.memory().dense_len(), 0);
+    let mut dense = sparse.clone();
+    dense.set_memory_backend(Backend::Dense);
+    assert_eq!(sparse.execute(&[]).unwrap().1, [42]);
+    assert_eq!(dense.execute(&[]).unwrap().1, [42]);
+    assert_eq!(sparse.memory(), dense.memory());
+    assert_eq!(dense.memory().dense_len(), 101);
+
+    let far = 1_000_000_000;
+    let mut written = Memory::from(vec![1, 2]);
+    *written.word_mut(far) = 0;
+    let mut resized = Memory::from(vec![1, 2]);
+    resized.resize(far + 1);
+    assert_eq!(written, resized);
+    assert_eq!(resized, written);
+    *written.word_mut(far) = 3;
+    assert_ne!(written, resized);
+}
+
+#[test]
+fn faults_report_where_they_happened() {
+    let mut p = parse("1101,2,3,7,204,-2,99");
+    let fault = p.execute(&[]).unwrap_err();
+    assert!(matches!(fault.error, ExecutionError::OutOfBounds));
+    assert_eq!(
+        (fault.pc, fault.instruction, fault.opcode, fault.address),
+        (4, Some(204), Some(Opcode::Print), Some(-2))
+    );
+    assert_eq!(fault.executed, 1);
+    assert_eq!(p.program_counter(), 4);
+    let shown = fault.to_string();
+    assert!(shown.starts_with(
+        "Attempted to read or write out of bounds (pc 4, instruction 204 `out`, address -2"
+    ));
+    assert!(shown.contains("=>      4: 204,-2"));
+    // Error chains would otherwise repeat the message.
+    assert!(std::error::Error::source(&fault).is_none());
+
+    let fault = parse("1,0,0,0,42").execute(&[]).unwrap_err();
+    assert!(matches!(fault.error, ExecutionError::UnknownOpcode(42)));
+    assert_eq!((fault.pc, fault.executed, fault.opcode), (4, 1, None));
+}
+
+#[test]
+fn strict_input_mode_fails_on_missing_input() {
+    let mut p = parse("3,9,3,10,1,9,10,11,99");
+    p.set_input_mode(InputMode::Strict);
+    let fault = p.execute(&[1]).unwrap_err();
+    assert!(matches!(
+        fault.error,
+        ExecutionError::UnexpectedEndOfInput { pc: 2 }
+    ));
+    assert_eq!(p.program_counter(), 2);
+
+    p.set_input_mode(InputMode::Pause);
+    assert_eq!(p.execute(&[]).unwrap().0, ProgramState::ExpectingInput);
+    assert_eq!(p.execute(&[2]).unwrap().0, ProgramState::Exited);
+    assert_eq!(p.code[11], 3);
+
+    // Missing input is asked for once, not again when reporting the fault.
+    let mut reads = 0;
+    let mut read = || -> Option<i64> {
+        reads += 1;
+        None
+    };
+    let mut p = parse("3,9,99");
+    p.set_input_mode(InputMode::Strict);
+    let fault = p.run(&mut read, &mut Vec::new()).unwrap_err();
+    assert_eq!((fault.pc, fault.address), (0, Some(9)));
+    assert_eq!(reads, 1);
+}
+
+#[test]
+fn run_until_output_yields_after_every_value() {
+    let mut p = parse("104,1,104,2,3,9,4,9,99,0");
+    let mut input = VecDeque::new();
+    let mut output = Vec::new();
+    assert_eq!(
+        p.run_until_output(&mut input, &mut output).unwrap(),
+        ProgramState::ProducedOutput
+    );
+    assert_eq!(output, [1]);
+    assert_eq!(
+        p.run_until_output(&mut input, &mut output).unwrap(),
+        ProgramState::ProducedOutput
+    );
+    assert_eq!(
+        p.run_until_output(&mut input, &mut output).unwrap(),
+        ProgramState::ExpectingInput
+    );
+    input.push_back(3);
+    assert_eq!(
+        p.run_until_output(&mut input, &mut output).unwrap(),
+        ProgramState::ProducedOutput
+    );
+    assert_eq!(
+        p.run_until_output(&mut input, &mut output).unwrap(),
+        ProgramState::Exited
+    );
+    assert_eq!(output, [1, 2, 3]);
+}
+
+const ECHO: &str = "
+        out #62             ; '>'
+loop:   in [c]
+        eq [c], #10, [nl]
+        jnz [nl], #done
+        out [c]
+        jnz #1, #loop
+done:   out #10
+        out #1000
+        halt
+c:      .data 0
+nl:     .data 0
+";
+
+#[test]
+fn ascii_programs_exchange_lines() {
+    let mut ascii = Ascii::new(asm::assemble_program(ECHO).unwrap());
+    assert_eq!(ascii.run().unwrap(), ProgramState::ExpectingInput);
+    assert_eq!(ascii.take_partial_line(), ">");
+    ascii.send_line("hello");
+    assert_eq!(ascii.run().unwrap(), ProgramState::Exited);
+    assert_eq!(ascii.take_lines(), ["hello"]);
+    assert_eq!(ascii.take_values(), [1000]);
+    assert_eq!(ascii::encode_line("ab").collect::<Vec<_>>(), [97, 98, 10]);
+}
+
+#[test]
+fn run_arguments_and_input_file_are_parsed() {
+    let args =
+        |args: &[&str]| RunArgs::parse(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+
+    let run = args(&["prog.ic", "1", "-2"]).unwrap();
+    assert_eq!(run.program, "prog.ic");
+    assert_eq!(run.values(), Ok(vec![1, -2]));
+    assert!(!run.ascii && !run.is_interactive());
+
+    let mut run = args(&["--input-file", "in.txt", "prog.ic", "--ascii"]).unwrap();
+    assert_eq!(run.input_file.as_deref(), Some("in.txt"));
+    assert!(run.ascii && !run.is_interactive());
+    run.add_inputs("north\ntake 1, 2\n");
+    assert_eq!(run.inputs, ["north", "take 1, 2"]);
+
+    let mut run = args(&["--input-file", "in.txt", "prog.ic", "5"]).unwrap();
+    run.add_inputs("1,2\n 3\n");
+    assert_eq!(run.values(), Ok(vec![5, 1, 2, 3]));
+    run.inputs.push("x".to_owned());
+    assert!(run.values().is_err());
+
+    assert!(args(&["prog.ic"]).unwrap().is_interactive());
+    assert_eq!(args(&["--ascii"]), Err(ArgsError::MissingProgram));
+    assert_eq!(
+        args(&["prog.ic", "--input-file"]),
+        Err(ArgsError::MissingInputFile)
+    );
+}
+
+#[test]
+fn profile_counts_loops_and_unreached_code() {
+    let mut p = asm::assemble_program(
+        "
+loop:   add [n], #-1, [n]
+        jnz [n], #loop
+        jz [n], #end
+        out [n]
+end:    halt
+n:      .data 3
+",
+    )
+    .unwrap();
+    let code = p.code.to_vec();
+    p.start_profile();
+    p.execute(&[]).unwrap();
+    let profile = p.take_profile().unwrap();
+    assert_eq!(profile.instructions(), 8);
+    assert_eq!(profile.executions[&0], 3);
+    assert_eq!(profile.writes[&13], 3);
+    assert_eq!(profile.reads[&13], 7);
+    assert_eq!(
+        profile.branches[&4],
+        profile::Branch {
+            taken: 2,
+            not_taken: 1
+        }
+    );
+
+    let report = profile.report(&code, p.dialect(), 5);
+    assert_eq!(report.hot_loops.len(), 1);
+    assert_eq!(
+        (report.hot_loops[0].start, report.hot_loops[0].jump),
+        (0, 4)
+    );
+    assert_eq!(report.hot_loops[0].iterations, 2);
+    assert_eq!(report.unexecuted, vec![10..12]);
+    assert_eq!(report.one_sided.len(), 1);
+    assert!(report.to_string().contains("Never executed:\n      10..12"));
+}
+
+#[test]
+fn cfg_splits_blocks_and_flags_computed_jumps() {
+    let code = asm::assemble(
+        "
+        in [x]
+        add #99, #0, [done]
+loop:   add [x], #-1, [x]
+        jnz [x], #loop
+        jz [x], #done
+        jnz #1, [x]
+done:   halt
+x:      .data 0
+",
+    )
+    .unwrap();
+    let cfg = Cfg::build(&code, &Dialect::<i64>::standard());
+    assert_eq!(
+        cfg.blocks.keys().copied().collect::<Vec<_>>(),
+        [0, 6, 13, 16, 19]
+    );
+
+    let edges = |start: usize| -> Vec<(usize, EdgeKind)> {
+        cfg.blocks[&start]
+            .successors
+            .iter()
+            .map(|edge| (edge.target, edge.kind))
+            .collect()
+    };
+    assert_eq!(edges(0), [(6, EdgeKind::FallThrough)]);
+    assert_eq!(edges(6), [(13, EdgeKind::FallThrough), (6, EdgeKind::Jump)]);
+    assert_eq!(
+        edges(13),
+        [(16, EdgeKind::FallThrough), (19, EdgeKind::Jump)]
+    );
+    assert_eq!(edges(16), []);
+    assert!(cfg.blocks[&16].computed_jump);
+    assert!(!cfg.blocks[&13].computed_jump);
+    assert_eq!(cfg.blocks[&0].code_writes, [19]);
+    assert_eq!(cfg.blocks[&6].end(), 13);
+
+    let dot = cfg.to_dot();
+    assert!(dot.contains("b6 -> b6 [label=\"jump\"];"));
+    assert!(dot.contains("b16 -> computed [style=dashed];"));
+    assert!(cfg
+        .to_string()
+        .contains("block 16..19:\n      16: jnz #1, [20]\n  -> ? (computed jump)"));
+}
+
+#[test]
+fn network_fans_out_and_reports_deadlocks() {
+    let mut network = Network::new();
+    let source = network.add(asm::assemble_program("out #21\nout #0\nhalt").unwrap());
+    for _ in 0..2 {
+        let doubler = network.add(asm::assemble_program(DOUBLER).unwrap());
+        network.connect(source, doubler);
+    }
+    // Only ever receives the 42, never the 0 that would stop it.
+    let stuck = network.add(asm::assemble_program(DOUBLER).unwrap());
+    network.connect(1, stuck);
+
+    let mut threaded = network.clone();
+    for result in [network.run(), threaded.run_threaded()] {
+        assert!(matches!(
+            result.unwrap_err(),
+            NetworkError::Deadlock { waiting } if waiting == [Waiting { machine: stuck, pc: 2 }]
+        ));
+    }
+    for network in [network, threaded] {
+        assert_eq!(network.outputs(1), [42]);
+        assert_eq!(network.outputs(2), [42]);
+        assert_eq!(network.outputs(stuck), [84]);
+        assert_eq!(network.state(2), Some(ProgramState::Exited));
+    }
+}
+
+#[test]
+fn packet_network_routes_packets_and_wakes_idle_network() {
+    let nic = asm::assemble_program(
+        "
+        in [addr]
+        jnz [addr], #recv
+        out #1
+        out #5
+        out #7
+recv:   in [x]
+        eq [x], #-1, [t]
+        jnz [t], #recv
+        in [y]
+        add [x], [y], [x]
+        out #255
+        out [x]
+        out [y]
+        jnz #1, #recv
+addr:   .data 0
+x:      .data 0
+y:      .data 0
+t:      .data 0
+",
+    )
+    .unwrap();
+    let mut network = PacketNetwork::boot(&nic, 2);
+    let packet = |destination, x, y| Packet { destination, x, y };
+
+    let round = network.round().unwrap();
+    assert_eq!(round.sent, [packet(1, 5, 7), packet(NAT, 12, 7)]);
+    assert!(!round.idle);
+    assert_eq!(network.nat(), Some(packet(NAT, 12, 7)));
+
+    let round = network.round().unwrap();
+    assert!(round.idle);
+    assert_eq!(round.woken, Some(packet(0, 12, 7)));
+
+    assert_eq!(network.round().unwrap().sent, [packet(NAT, 19, 7)]);
+    assert_eq!(network.round().unwrap().woken, Some(packet(0, 19, 7)));
+}
+
+#[test]
+fn decode_cache_sees_modified_instructions() {
+    for cached in [true, false] {
+        // Turns its first instruction into an output of address 7.
+        let mut p = parse("104,7,1101,4,0,0,1105,1,0");
+        p.set_decode_cache(cached);
+        let mut output = Vec::new();
+        for _ in 0..2 {
+            assert_eq!(
+                p.run_until_output(&mut VecDeque::new(), &mut output)
+                    .unwrap(),
+                ProgramState::ProducedOutput
+            );
+        }
+        assert_eq!(output, [7, 1]);
+        p.code[2] = 99;
+        assert_eq!(p.execute(&[]).unwrap(), (ProgramState::Exited, vec![]));
+        assert_eq!(p.program_counter(), 2);
+
+        // More writes than the cache keeps track of individually.
+        let mut p = parse("1,0,0,0,99");
+        p.set_decode_cache(cached);
+        let mut q = p.clone();
+        for (address, value) in [(1, 5), (2, 6), (1, 4), (2, 4), (3, 0), (1, 1)] {
+            q.code[address] = value;
+        }
+        q.execute(&[]).unwrap();
+        assert_eq!(q.code[0], 100);
+        p.execute(&[]).unwrap();
+        assert_eq!(p.code[0], 2);
+    }
+}
+
+#[test]
+fn dialects_add_replace_and_remove_opcodes() {
+    let mut dialect = Dialect::standard();
+    let modulo = CustomOperation::new("mod", 3, &[2], |machine| {
+        let lhs: i64 = machine.read()?;
+        let rhs = machine.read()?;
+        let target = machine.target()?;
+        let result = lhs
+            .checked_rem(rhs)
+            .ok_or_else(|| ExecutionError::Custom("division by zero".to_owned()))?;
+        machine.write(target, result)
+    });
+    dialect.register(10, modulo).unwrap();
+    // Prints its parameter twice and skips the next instruction.
+    let twice = CustomOperation::new("twice", 1, &[], |machine| {
+        let value = machine.read()?;
+        machine.output(value);
+        machine.output(value);
+        machine.jump(machine.pc() as i64 + 3)
+    });
+    dialect.register(4, twice).unwrap();
+
+    let mut p = parse("1110,17,5,0,4,0,99,99");
+    p.set_dialect(dialect.clone());
+    p.start_trace();
+    assert_eq!(p.execute(&[]).unwrap(), (ProgramState::Exited, vec![2, 2]));
+    assert_eq!(p.program_counter(), 7);
+    let recorded = p.take_trace().unwrap();
+    let mut bytes = Vec::new();
+    recorded.write_to(&mut bytes).unwrap();
+    assert!(Trace::read_from(bytes.as_slice()).is_err());
+    let loaded = Trace::read_with_dialect(bytes.as_slice(), &dialect).unwrap();
+    assert_eq!(loaded, recorded);
+    assert_eq!(loaded.entries[0].operands, [17, 5]);
+    assert_eq!(loaded.entries[1].opcode.mnemonic(), "twice");
+    let code = parse("1110,17,5,0,4,0,99,99").code.to_vec();
+    let listing: Vec<String> = disasm::disassemble(&code, &dialect)
+        .iter()
+        .map(disasm::Line::source)
+        .collect();
+    assert_eq!(
+        listing,
+        ["mod #17, #5, [0]", "twice [0]", "halt", ".data 99"]
+    );
+    assert_eq!(dialect.opcode(10).unwrap().mnemonic(), "mod");
+
+    let mut p = parse("1110,3,0,0,99");
+    p.set_dialect(dialect.clone());
+    let fault = p.execute(&[]).unwrap_err();
+    assert!(matches!(fault.error, ExecutionError::Custom(_)));
+    assert_eq!(fault.error.to_string(), "division by zero");
+    assert_eq!(fault.opcode.map(|opcode| opcode.mnemonic()), Some("mod"));
+    assert_eq!(fault.window[0].source(), "mod #3, #0, [0]");
+
+    dialect.remove(10);
+    let mut p = parse("1110,17,5,0,99");
+    p.set_dialect(dialect);
+    assert!(matches!(
+        p.execute(&[]).unwrap_err().error,
+        ExecutionError::UnknownOpcode(10)
+    ));
+    assert_eq!(
+        Dialect::<i64>::standard().register(100, Opcode::Exit),
+        Err(DialectError::InvalidCode(100))
+    );
+
+    let mut greedy = Dialect::standard();
+    // Declares one parameter but fetches two.
+    let reads_twice = CustomOperation::new("greedy", 1, &[], |machine| {
+        let first: i64 = machine.read()?;
+        let second: i64 = machine.read()?;
+        machine.output(first + second);
+        Ok(())
+    });
+    greedy.register(10, reads_twice).unwrap();
+    let mut p = parse("110,7,99");
+    p.set_dialect(greedy);
+    let fault = p.execute(&[]).unwrap_err();
+    assert!(matches!(
+        fault.error,
+        ExecutionError::TooManyParameters { pc: 0 }
+    ));
+    assert_eq!(fault.opcode.map(|opcode| opcode.mnemonic()), Some("greedy"));
+}
+
+#[test]
+fn snapshots_restore_with_a_dialect() {
+    let mut dialect = Dialect::standard();
+    let negate = CustomOperation::new("neg", 2, &[1], |machine| {
+        let value: i64 = machine.read()?;
+        let target = machine.target()?;
+        machine.write(target, -value)
+    });
+    dialect.register(10, negate).unwrap();
+    let mut p = parse("104,1,110,5,0,4,0,99");
+    p.set_dialect(dialect.clone());
+    p.step(&mut io::Iter(std::iter::empty()), &mut Vec::new())
+        .unwrap();
+    let snapshot = p.snapshot();
+
+    assert!(matches!(
+        Program::restore(&snapshot)
+            .unwrap()
+            .execute(&[])
+            .unwrap_err()
+            .error,
+        ExecutionError::UnknownOpcode(10)
+    ));
+    let mut restored = Program::restore_with_dialect(&snapshot, &dialect).unwrap();
+    assert_eq!(restored.execute(&[]).unwrap(), p.execute(&[]).unwrap());
+    assert_eq!(restored.code[0], -5);
+}
+
+#[test]
+fn devices_take_over_their_addresses() {
+    let mut p = asm::assemble_program(
+        "
+        add #0, #0, [100]   ; restart the cycle counter
+        add [100], #0, [x]
+        out [x]
+        add #6, #0, [101]   ; roll dice
+        out [101]
+        out [101]
+        add #1, #0, [110]
+        add [x], #0, [114]
+        add #0, #0, [116]   ; render
+        halt
+x:      .data 0
+",
+    )
+    .unwrap();
+    p.attach(100..101, CycleCounter::new()).unwrap();
+    p.attach(101..102, Random::new(7)).unwrap();
+    let screen = p
+        .attach(110..117, Framebuffer::new(3, 2, Vec::new()))
+        .unwrap();
+    assert_eq!(
+        p.attach(105..111, CycleCounter::new()).unwrap_err(),
+        DeviceError::Overlap {
+            range: 105..111,
+            existing: 110..117
+        }
+    );
+
+    let mut dice = Random::new(7);
+    Device::<i64>::write(&mut dice, 0, 6, 0);
+    let rolls: Vec<i64> = (0..2).map(|_| dice.read(0, 0)).collect();
+    assert!(rolls.iter().all(|roll| (0..6).contains(roll)));
+    let (state, output) = p.execute(&[]).unwrap();
+    assert_eq!(state, ProgramState::Exited);
+    assert_eq!(output, [1, rolls[0], rolls[1]]);
+
+    let screen = screen.lock().unwrap();
+    assert_eq!(screen.pixel(0, 0), 1);
+    assert_eq!(screen.pixel(1, 1), 1);
+    assert_eq!(screen.sink(), b"#  \n # \n");
+    assert_eq!(p.code.get(110), None);
+}
+
+#[test]
+fn cycle_counter_is_shared_with_clones() {
+    let mut p = asm::assemble_program(
+        "
+        in [x]
+        jz [x], #read
+        add #0, #0, [x]
+        add #0, #0, [100]
+read:   out [100]
+        halt
+x:      .data 0
+",
+    )
+    .unwrap();
+    p.attach(100..101, CycleCounter::new()).unwrap();
+    let mut behind = p.clone();
+    assert_eq!(p.execute(&[1]).unwrap().1, [1]);
+    // The counter was reset after the third instruction, but the clone reads
+    // it after its second.
+    assert_eq!(behind.execute(&[0]).unwrap().1, [0]);
+}
+
+#[cfg(feature = "bigint")]
+#[test]
+fn bigint_words_do_not_overflow() {
+    use aoc19::intcode::word::BigInt;
+
+    let big = BigInt::from(i64::MAX);
+    let source = format!("1002,9,{big},9,4,9,99,0,0,{big}");
+    let mut p: Program<BigInt> = source.parse().unwrap();
+    let square = &big * &big;
+    assert_eq!(
+        p.execute(&[]).unwrap(),
+        (ProgramState::Exited, vec![square.clone()])
+    );
+    assert_eq!(p.code[9], square);
+
+    let mut p: Program<BigInt> = format!("4,{square},99").parse().unwrap();
+    assert!(matches!(
+        p.execute(&[]).map_err(|fault| fault.error),
+        Err(ExecutionError::WordOutOfRange)
+    ));
+}